@@ -0,0 +1,176 @@
+//! Rolling fields up into min, max and mean over fixed windows.
+
+use crate::reading::Record;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Running min, max and mean of one field.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub min: f64,
+    pub max: f64,
+    sum: f64,
+    count: u32,
+}
+
+impl Summary {
+    pub fn new(value: f64) -> Self {
+        Summary {
+            min: value,
+            max: value,
+            sum: value,
+            count: 1,
+        }
+    }
+
+    pub fn push(&mut self, value: f64) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.count += 1;
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.sum / self.count as f64
+    }
+}
+
+/// Accumulates the fields of records into windows aligned to multiples of the window length
+/// since the epoch, so hourly windows start on the hour.
+#[derive(Debug, Clone)]
+pub struct Aggregator {
+    /// Window length in nanoseconds.
+    window: i64,
+    /// Start of the current window, in nanoseconds since the epoch.
+    started: Option<i64>,
+    /// Summaries by measurement and field tags, since fields like gas resistance at different
+    /// heater temperatures aren't comparable.
+    fields: BTreeMap<(String, BTreeMap<String, String>), Summary>,
+}
+
+impl Aggregator {
+    pub fn new(window: Duration) -> Self {
+        Aggregator {
+            window: (window.as_nanos() as i64).max(1),
+            started: None,
+            fields: BTreeMap::new(),
+        }
+    }
+
+    /// Add the fields of `record`. When it falls into a later window than the fields so far,
+    /// the finished window is returned as a record tagged `tags` and the accumulators start
+    /// over with `record`.
+    pub fn push(&mut self, record: &Record, tags: &BTreeMap<String, String>) -> Option<Record> {
+        let started = record.timestamp - record.timestamp.rem_euclid(self.window);
+        let finished = match self.started {
+            Some(current) if current != started => self.take(tags),
+            _ => None,
+        };
+        self.started = Some(started);
+        for field in &record.fields {
+            if !field.value.is_finite() {
+                continue;
+            }
+            self.fields
+                .entry((field.measurement.clone(), field.tags.clone()))
+                .and_modify(|summary| summary.push(field.value))
+                .or_insert_with(|| Summary::new(field.value));
+        }
+        finished
+    }
+
+    /// End the current window, returning its `*_min`, `*_max` and `*_mean` fields timestamped
+    /// at the start of the window, or `None` when nothing was added to it.
+    pub fn take(&mut self, tags: &BTreeMap<String, String>) -> Option<Record> {
+        let started = self.started.take()?;
+        if self.fields.is_empty() {
+            return None;
+        }
+        let mut record = Record::new(started, tags.clone());
+        for ((measurement, field_tags), summary) in std::mem::take(&mut self.fields) {
+            for (suffix, value) in [
+                ("min", summary.min),
+                ("max", summary.max),
+                ("mean", summary.mean()),
+            ]
+            .iter()
+            {
+                record.push_tagged(
+                    &format!("{}_{}", measurement, suffix),
+                    *value,
+                    field_tags.clone(),
+                );
+            }
+        }
+        Some(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: i64 = 60_000_000_000;
+
+    fn record(timestamp: i64, temperature_c: f64) -> Record {
+        let mut record = Record::new(timestamp, BTreeMap::new());
+        record.push("temperature_c", temperature_c);
+        record
+    }
+
+    fn values(record: &Record) -> Vec<(&str, f64)> {
+        record
+            .fields
+            .iter()
+            .map(|field| (field.measurement.as_str(), field.value))
+            .collect()
+    }
+
+    #[test]
+    fn summary_of_a_known_sequence() {
+        let mut summary = Summary::new(20.0);
+        for &value in &[18.5, 23.0, 22.5] {
+            summary.push(value);
+        }
+        assert_eq!(
+            (summary.min, summary.max, summary.mean()),
+            (18.5, 23.0, 21.0)
+        );
+    }
+
+    #[test]
+    fn windows_close_when_a_reading_falls_into_the_next() {
+        let mut aggregator = Aggregator::new(Duration::from_secs(3600));
+        let tags = BTreeMap::new();
+        let hour = 60 * MINUTE;
+        assert_eq!(
+            aggregator.push(&record(5 * hour + MINUTE, 20.0), &tags),
+            None
+        );
+        assert_eq!(
+            aggregator.push(&record(5 * hour + 30 * MINUTE, 24.0), &tags),
+            None
+        );
+        assert_eq!(
+            aggregator.push(&record(5 * hour + 59 * MINUTE, f64::NAN), &tags),
+            None
+        );
+
+        let finished = aggregator.push(&record(6 * hour, 30.0), &tags).unwrap();
+        assert_eq!(finished.timestamp, 5 * hour);
+        assert_eq!(
+            values(&finished),
+            vec![
+                ("temperature_c_min", 20.0),
+                ("temperature_c_max", 24.0),
+                ("temperature_c_mean", 22.0),
+            ]
+        );
+
+        // The accumulators started over with the reading that closed the window.
+        let partial = aggregator.take(&tags).unwrap();
+        assert_eq!(partial.timestamp, 6 * hour);
+        assert_eq!(values(&partial)[2], ("temperature_c_mean", 30.0));
+        assert_eq!(aggregator.take(&tags), None);
+    }
+}
@@ -0,0 +1,250 @@
+//! Threshold alerts posted to a webhook.
+//!
+//! An alert fires once when a value crosses out of its bounds and re-arms when the value is back
+//! within them, so a reading that stays out of bounds doesn't repeat the alert every cycle.
+
+use crate::config::AlertConfig;
+use crate::reading::Reading;
+use serde_json::json;
+use std::fmt;
+use tracing::{error, info};
+
+/// A reading value that can be alerted on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlertField {
+    Temperature,
+    Humidity,
+    Pressure,
+    Gas,
+}
+
+impl AlertField {
+    pub fn name(self) -> &'static str {
+        match self {
+            AlertField::Temperature => "temperature_c",
+            AlertField::Humidity => "relative_humidity",
+            AlertField::Pressure => "pressure_hpa",
+            AlertField::Gas => "gas_resistance_ohms",
+        }
+    }
+
+    fn value(self, reading: &Reading) -> Option<f64> {
+        match self {
+            AlertField::Temperature => Some(reading.temperature_c),
+            AlertField::Humidity => Some(reading.humidity_percent),
+            AlertField::Pressure => Some(reading.pressure_hpa),
+            AlertField::Gas => reading.gas_ohms,
+        }
+    }
+}
+
+/// Which side of a threshold was crossed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bound {
+    Min,
+    Max,
+}
+
+impl Bound {
+    fn name(self) -> &'static str {
+        match self {
+            Bound::Min => "min",
+            Bound::Max => "max",
+        }
+    }
+}
+
+/// Bounds for one field, with the state needed to fire only on crossings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Threshold {
+    pub field: AlertField,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    tripped: bool,
+}
+
+/// A threshold crossing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Alert {
+    pub field: AlertField,
+    pub value: f64,
+    pub bound: Bound,
+    pub limit: f64,
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} crossed the {} threshold of {}",
+            self.field.name(),
+            self.value,
+            self.bound.name(),
+            self.limit
+        )
+    }
+}
+
+impl Threshold {
+    pub fn new(field: AlertField, min: Option<f64>, max: Option<f64>) -> Self {
+        Threshold {
+            field,
+            min,
+            max,
+            tripped: false,
+        }
+    }
+
+    /// Check a value against the bounds. Returns an alert only when the value leaves the bounds
+    /// while armed; the threshold re-arms once a value is back within them.
+    pub fn evaluate(&mut self, value: f64) -> Option<Alert> {
+        let crossed = match (self.min, self.max) {
+            (Some(min), _) if value < min => Some((Bound::Min, min)),
+            (_, Some(max)) if value > max => Some((Bound::Max, max)),
+            _ => None,
+        };
+        match crossed {
+            Some((bound, limit)) if !self.tripped => {
+                self.tripped = true;
+                Some(Alert {
+                    field: self.field,
+                    value,
+                    bound,
+                    limit,
+                })
+            }
+            Some(_) => None,
+            None => {
+                self.tripped = false;
+                None
+            }
+        }
+    }
+}
+
+/// Evaluates thresholds for each reading and posts crossings to a webhook.
+pub struct Alerter {
+    http: reqwest::Client,
+    url: String,
+    host: String,
+    sensor: Option<String>,
+    thresholds: Vec<Threshold>,
+}
+
+impl Alerter {
+    /// Build an alerter from the config, or `None` when no webhook is configured. `sensor` labels
+    /// the alerts when several sensors are read.
+    pub fn from_config(config: &AlertConfig, host: &str, sensor: Option<&str>) -> Option<Alerter> {
+        let url = config.webhook_url.clone()?;
+        let thresholds: Vec<Threshold> = [
+            (
+                AlertField::Temperature,
+                config.temp_min_c,
+                config.temp_max_c,
+            ),
+            (
+                AlertField::Humidity,
+                config.humidity_min_percent,
+                config.humidity_max_percent,
+            ),
+            (
+                AlertField::Pressure,
+                config.pressure_min_hpa,
+                config.pressure_max_hpa,
+            ),
+            (AlertField::Gas, config.gas_min_ohms, config.gas_max_ohms),
+        ]
+        .iter()
+        .filter(|(_, min, max)| min.is_some() || max.is_some())
+        .map(|&(field, min, max)| Threshold::new(field, min, max))
+        .collect();
+        info!(
+            "Posting alerts for {} thresholds to {}",
+            thresholds.len(),
+            url
+        );
+        Some(Alerter {
+            http: reqwest::Client::new(),
+            url,
+            host: host.to_string(),
+            sensor: sensor.map(str::to_string),
+            thresholds,
+        })
+    }
+
+    /// Evaluate every threshold and post any new crossings in the background, returning them.
+    pub fn check(&mut self, reading: &Reading) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        for threshold in self.thresholds.iter_mut() {
+            let alert = match threshold.field.value(reading) {
+                Some(value) => threshold.evaluate(value),
+                None => None,
+            };
+            if let Some(alert) = alert {
+                info!("{}", alert);
+                alerts.push(alert);
+                let mut payload = json!({
+                    "host": self.host,
+                    "timestamp": reading.timestamp,
+                    "field": alert.field.name(),
+                    "value": alert.value,
+                    "bound": alert.bound.name(),
+                    "limit": alert.limit,
+                });
+                if let Some(sensor) = &self.sensor {
+                    payload["sensor"] = json!(sensor);
+                }
+                let request = self
+                    .http
+                    .post(&self.url)
+                    .header("Content-Type", "application/json")
+                    .body(payload.to_string());
+                tokio::spawn(async move {
+                    match request.send().await {
+                        Ok(response) if !response.status().is_success() => {
+                            error!("Alert webhook returned {}", response.status())
+                        }
+                        Ok(_) => {}
+                        Err(e) => error!("Failed to post alert: {}", e),
+                    }
+                });
+            }
+        }
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alert_fires_once_per_crossing() {
+        let mut threshold = Threshold::new(AlertField::Temperature, None, Some(30.0));
+        assert_eq!(threshold.evaluate(29.0), None);
+        assert_eq!(
+            threshold.evaluate(31.0),
+            Some(Alert {
+                field: AlertField::Temperature,
+                value: 31.0,
+                bound: Bound::Max,
+                limit: 30.0,
+            })
+        );
+        assert_eq!(threshold.evaluate(32.0), None);
+        assert_eq!(threshold.evaluate(31.0), None);
+    }
+
+    #[test]
+    fn alert_rearms_once_back_within_bounds() {
+        let mut threshold = Threshold::new(AlertField::Humidity, Some(30.0), Some(60.0));
+        assert!(threshold.evaluate(25.0).is_some());
+        // Exactly on the limit is still within bounds.
+        assert_eq!(threshold.evaluate(30.0), None);
+        let alert = threshold.evaluate(65.0).unwrap();
+        assert_eq!((alert.bound, alert.limit), (Bound::Max, 60.0));
+        assert_eq!(threshold.evaluate(70.0), None);
+        assert_eq!(threshold.evaluate(50.0), None);
+        assert!(threshold.evaluate(29.9).is_some());
+    }
+}
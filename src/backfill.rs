@@ -0,0 +1,202 @@
+//! Importing readings from files written by the CSV sink.
+
+use crate::reading::Record;
+use crate::sink::ReadingSink;
+use chrono::DateTime;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Columns that always hold tags rather than measurements.
+pub const TAG_COLUMNS: [&str; 3] = ["host", "sensor", "quality"];
+
+/// Split a CSV row, undoing the quoting of values that contain commas or quotes.
+pub fn split_row(line: &str) -> Result<Vec<String>, String> {
+    let mut values = Vec::new();
+    let mut value = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                value.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if value.is_empty() => quoted = true,
+            ',' if !quoted => values.push(std::mem::take(&mut value)),
+            c => value.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quote".to_string());
+    }
+    values.push(value);
+    Ok(values)
+}
+
+/// Nanoseconds since the epoch from an RFC 3339 timestamp as written by the CSV sink.
+pub fn parse_timestamp(value: &str) -> Result<i64, String> {
+    let time = DateTime::parse_from_rfc3339(value)
+        .map_err(|e| format!("invalid timestamp {:?}: {}", value, e))?;
+    time.timestamp_nanos_opt()
+        .ok_or_else(|| format!("timestamp {:?} is out of range", value))
+}
+
+/// Turn one data row into a record. Columns named in `tag_columns` become tags, every other
+/// non-empty value must be a number.
+pub fn parse_row(
+    header: &[String],
+    line: &str,
+    tag_columns: &BTreeSet<String>,
+) -> Result<Record, String> {
+    let values = split_row(line)?;
+    if values.len() != header.len() {
+        return Err(format!(
+            "expected {} columns, found {}",
+            header.len(),
+            values.len()
+        ));
+    }
+    let mut timestamp = None;
+    let mut record = Record::new(0, Default::default());
+    for (column, value) in header.iter().zip(values) {
+        if column == "timestamp" {
+            timestamp = Some(parse_timestamp(&value)?);
+        } else if value.is_empty() {
+            continue;
+        } else if tag_columns.contains(column) {
+            record.tags.insert(column.clone(), value);
+        } else {
+            let number = value
+                .parse::<f64>()
+                .map_err(|e| format!("invalid {} {:?}: {}", column, value, e))?;
+            record.push(column, number);
+        }
+    }
+    record.timestamp = timestamp.ok_or_else(|| "no timestamp column".to_string())?;
+    Ok(record)
+}
+
+/// Parse a whole file. Malformed rows are skipped with a warning and counted.
+pub fn parse_csv(
+    contents: &str,
+    tag_columns: &BTreeSet<String>,
+) -> Result<(Vec<Record>, usize), String> {
+    let mut lines = contents.lines();
+    let header = match lines.next() {
+        Some(line) => split_row(line)?,
+        None => return Err("empty file".to_string()),
+    };
+    if !header.iter().any(|column| column == "timestamp") {
+        return Err("header has no timestamp column".to_string());
+    }
+    let mut records = Vec::new();
+    let mut skipped = 0;
+    for (index, line) in lines.enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_row(&header, line, tag_columns) {
+            Ok(record) => records.push(record),
+            Err(e) => {
+                // Line numbers are 1-based and the header is line 1.
+                warn!("Skipping line {}: {}", index + 2, e);
+                skipped += 1;
+            }
+        }
+    }
+    Ok((records, skipped))
+}
+
+/// Write the readings in the CSV file at `path` to `sink` with their original timestamps and
+/// flush it, returning how many were imported.
+pub async fn backfill(
+    path: &Path,
+    sink: &mut dyn ReadingSink,
+    tag_columns: &BTreeSet<String>,
+) -> Result<usize, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let (records, skipped) =
+        parse_csv(&contents, tag_columns).map_err(|e| format!("{}: {}", path.display(), e))?;
+    info!(
+        "Backfilling {} readings from {} to {}, skipped {} malformed rows.",
+        records.len(),
+        path.display(),
+        sink.name(),
+        skipped
+    );
+    for record in &records {
+        sink.write_reading(record)
+            .await
+            .map_err(|e| format!("failed to write to {}: {}", sink.name(), e))?;
+    }
+    sink.flush()
+        .await
+        .map_err(|e| format!("failed to flush {}: {}", sink.name(), e))?;
+    Ok(records.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reading::Field;
+
+    fn tag_columns() -> BTreeSet<String> {
+        TAG_COLUMNS
+            .iter()
+            .map(|column| column.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn csv_rows_become_records_with_their_timestamps() {
+        let csv = "timestamp,host,temperature_c,relative_humidity,gas_resistance_ohms\n\
+                   2023-06-01T12:00:00.000000001Z,\"pi,attic\",21.5,45,\n\
+                   \n\
+                   2023-06-01T12:01:00Z,pi,21.75,44.5,51000\n";
+        let (records, skipped) = parse_csv(csv, &tag_columns()).unwrap();
+        assert_eq!(skipped, 0);
+        assert_eq!(records.len(), 2);
+
+        assert_eq!(records[0].timestamp, 1_685_620_800_000_000_001);
+        assert_eq!(
+            records[0].tags.get("host").map(String::as_str),
+            Some("pi,attic")
+        );
+        assert_eq!(
+            records[0].fields,
+            vec![
+                Field::new("temperature_c", 21.5),
+                Field::new("relative_humidity", 45.0),
+            ]
+        );
+        assert_eq!(records[1].timestamp, 1_685_620_860_000_000_000);
+        assert_eq!(records[1].fields.len(), 3);
+    }
+
+    #[test]
+    fn malformed_rows_are_skipped_and_counted() {
+        let csv = "timestamp,host,temperature_c\n\
+                   2023-06-01T12:00:00Z,pi,21.5\n\
+                   yesterday,pi,21.5\n\
+                   2023-06-01T12:02:00Z,pi,warm\n\
+                   2023-06-01T12:03:00Z,pi\n\
+                   2023-06-01T12:04:00Z,\"pi,21.5\n";
+        let (records, skipped) = parse_csv(csv, &tag_columns()).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(skipped, 4);
+        assert!(parse_csv("host,temperature_c\npi,21.5\n", &tag_columns()).is_err());
+        assert!(parse_csv("", &tag_columns()).is_err());
+    }
+
+    #[test]
+    fn timestamps_outside_the_nanosecond_range_are_errors() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:01Z"), Ok(1_000_000_000));
+        let err = parse_timestamp("2500-01-01T00:00:00Z").unwrap_err();
+        assert!(err.contains("out of range"), "{}", err);
+        assert!(parse_timestamp("yesterday").is_err());
+    }
+}
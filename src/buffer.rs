@@ -0,0 +1,170 @@
+//! Bounded local storage for points that could not be written yet, and batching of writes.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// FIFO buffer holding at most `capacity` items, discarding the oldest once full.
+#[derive(Debug, Clone)]
+pub struct PointBuffer<T> {
+    points: VecDeque<T>,
+    capacity: usize,
+}
+
+impl<T> PointBuffer<T> {
+    pub fn new(capacity: usize) -> Self {
+        PointBuffer {
+            points: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Append points in order, returning how many of the oldest had to be dropped to fit.
+    pub fn extend(&mut self, points: impl IntoIterator<Item = T>) -> usize {
+        let mut dropped = 0;
+        for point in points {
+            if self.capacity == 0 {
+                dropped += 1;
+                continue;
+            }
+            if self.points.len() == self.capacity {
+                self.points.pop_front();
+                dropped += 1;
+            }
+            self.points.push_back(point);
+        }
+        dropped
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.points.iter()
+    }
+
+    /// Keep only the points `keep` returns true for.
+    pub fn retain(&mut self, keep: impl FnMut(&T) -> bool) {
+        self.points.retain(keep);
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+}
+
+/// Decides when accumulated readings are written: once `size` readings are pending, or once the
+/// oldest pending one has waited `max_age`.
+#[derive(Debug, Clone)]
+pub struct Batch {
+    size: usize,
+    max_age: Duration,
+    pending: usize,
+    started: Option<Instant>,
+}
+
+impl Batch {
+    pub fn new(size: usize, max_age: Duration) -> Self {
+        Batch {
+            size: size.max(1),
+            max_age,
+            pending: 0,
+            started: None,
+        }
+    }
+
+    /// Count a reading added at `now`, returning whether the batch is due.
+    pub fn add(&mut self, now: Instant) -> bool {
+        if self.pending == 0 {
+            self.started = Some(now);
+        }
+        self.pending += 1;
+        self.is_due(now)
+    }
+
+    pub fn is_due(&self, now: Instant) -> bool {
+        if self.pending == 0 {
+            return false;
+        }
+        let expired = match self.started {
+            Some(started) => now.saturating_duration_since(started) >= self.max_age,
+            None => false,
+        };
+        self.pending >= self.size || expired
+    }
+
+    /// Start a new batch after a successful write.
+    pub fn reset(&mut self) {
+        self.pending = 0;
+        self.started = None;
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_buffer_drops_the_oldest_points() {
+        let mut buffer = PointBuffer::new(3);
+        assert_eq!(buffer.extend(vec![1, 2]), 0);
+        assert_eq!(buffer.extend(vec![3, 4, 5]), 2);
+        assert_eq!(buffer.iter().copied().collect::<Vec<_>>(), vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn points_buffered_across_failures_stay_in_order() {
+        let mut buffer = PointBuffer::new(10);
+        buffer.extend(vec![1]);
+        buffer.extend(vec![2]);
+        buffer.extend(vec![3]);
+        assert_eq!(buffer.iter().copied().collect::<Vec<_>>(), vec![1, 2, 3]);
+        buffer.clear();
+        assert_eq!(buffer.len(), 0);
+    }
+
+    #[test]
+    fn buffer_without_capacity_drops_everything() {
+        let mut buffer = PointBuffer::new(0);
+        assert_eq!(buffer.extend(vec![1, 2]), 2);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn batch_is_due_once_full() {
+        let now = Instant::now();
+        let mut batch = Batch::new(3, Duration::from_secs(60));
+        assert!(!batch.add(now));
+        assert!(!batch.add(now));
+        assert!(batch.add(now));
+        batch.reset();
+        assert_eq!(batch.pending(), 0);
+        assert!(!batch.add(now));
+    }
+
+    #[test]
+    fn batch_is_due_once_its_oldest_reading_is_too_old() {
+        let start = Instant::now();
+        let mut batch = Batch::new(10, Duration::from_secs(60));
+        assert!(!batch.add(start));
+        assert!(!batch.add(start + Duration::from_secs(30)));
+        assert!(!batch.is_due(start + Duration::from_secs(59)));
+        assert!(batch.is_due(start + Duration::from_secs(60)));
+        assert!(batch.add(start + Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn batch_of_one_is_always_due() {
+        let mut batch = Batch::new(0, Duration::from_secs(60));
+        assert!(batch.add(Instant::now()));
+        assert!(!Batch::new(1, Duration::from_secs(0)).is_due(Instant::now()));
+    }
+}
@@ -0,0 +1,135 @@
+//! Linear corrections for sensors that drift by different amounts across their range.
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// `corrected = raw * gain + offset`, from two reference points.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinearCalibration {
+    pub gain: f64,
+    pub offset: f64,
+}
+
+impl LinearCalibration {
+    pub fn new(gain: f64, offset: f64) -> Self {
+        LinearCalibration { gain, offset }
+    }
+
+    pub fn apply(&self, raw: f64) -> f64 {
+        raw * self.gain + self.offset
+    }
+
+    /// True when the correction leaves values unchanged.
+    pub fn is_identity(&self) -> bool {
+        self.gain == 1.0 && self.offset == 0.0
+    }
+}
+
+/// Corrections for one sensor in a calibration file. Unset values leave readings unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CalibrationEntry {
+    pub temperature_gain: f64,
+    pub temperature_offset: f64,
+    pub humidity_gain: f64,
+    pub humidity_offset: f64,
+}
+
+impl Default for CalibrationEntry {
+    fn default() -> Self {
+        CalibrationEntry {
+            temperature_gain: 1.0,
+            temperature_offset: 0.0,
+            humidity_gain: 1.0,
+            humidity_offset: 0.0,
+        }
+    }
+}
+
+impl CalibrationEntry {
+    pub fn temperature(&self) -> LinearCalibration {
+        LinearCalibration::new(self.temperature_gain, self.temperature_offset)
+    }
+
+    pub fn humidity(&self) -> LinearCalibration {
+        LinearCalibration::new(self.humidity_gain, self.humidity_offset)
+    }
+}
+
+/// Calibration for a fleet of sensors, a JSON object of [`CalibrationEntry`]s keyed by sensor
+/// label or host, so one file can be shipped to every host.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct CalibrationFile {
+    pub entries: BTreeMap<String, CalibrationEntry>,
+}
+
+impl CalibrationFile {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        Self::parse(&contents).map_err(|e| format!("invalid {}: {}", path.display(), e))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        serde_json::from_str(contents).map_err(|e| e.to_string())
+    }
+
+    /// The entry for the sensor labeled `label`, else the one for `host`.
+    pub fn lookup(&self, label: Option<&str>, host: &str) -> Option<&CalibrationEntry> {
+        label
+            .and_then(|label| self.entries.get(label))
+            .or_else(|| self.entries.get(host))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear_calibration_corrects_both_reference_points() {
+        // Reads 0.6 °C high at 20 °C and 1.1 °C high at 35 °C.
+        let gain = (35.0 - 20.0) / (36.1 - 20.6);
+        let calibration = LinearCalibration::new(gain, 20.0 - 20.6 * gain);
+        assert!((calibration.apply(20.6) - 20.0).abs() < 1e-9);
+        assert!((calibration.apply(36.1) - 35.0).abs() < 1e-9);
+        assert!(!calibration.is_identity());
+    }
+
+    #[test]
+    fn default_calibration_leaves_values_unchanged() {
+        let entry = CalibrationEntry::default();
+        assert!(entry.temperature().is_identity());
+        assert!(entry.humidity().is_identity());
+        assert_eq!(entry.temperature().apply(-12.5), -12.5);
+        assert_eq!(LinearCalibration::new(1.0, -1.5).apply(21.5), 20.0);
+    }
+
+    #[test]
+    fn calibration_file_is_looked_up_by_label_then_host() {
+        let file = CalibrationFile::parse(
+            r#"{
+                "outdoor": {"temperature_offset": -0.4},
+                "office-pi": {"temperature_gain": 0.97, "humidity_offset": 2.5}
+            }"#,
+        )
+        .unwrap();
+        let outdoor = file.lookup(Some("outdoor"), "office-pi").unwrap();
+        assert_eq!(outdoor.temperature(), LinearCalibration::new(1.0, -0.4));
+        assert!(outdoor.humidity().is_identity());
+        let host = file.lookup(Some("indoor"), "office-pi").unwrap();
+        assert_eq!(host.temperature(), LinearCalibration::new(0.97, 0.0));
+        assert_eq!(host.humidity(), LinearCalibration::new(1.0, 2.5));
+        assert_eq!(file.lookup(None, "garage-pi"), None);
+    }
+
+    #[test]
+    fn invalid_calibration_files_are_errors() {
+        assert!(CalibrationFile::parse("{\"outdoor\": ").is_err());
+        assert!(CalibrationFile::parse(r#"{"outdoor": {"temp_offset": 1}}"#).is_err());
+        assert!(CalibrationFile::parse(r#"{"outdoor": {"temperature_offset": "1"}}"#).is_err());
+    }
+}
@@ -0,0 +1,1756 @@
+//! The read loop: turning sensor readings into records and delivering them.
+
+use crate::aggregate::Aggregator;
+use crate::alert::Alerter;
+use crate::calibration::{CalibrationFile, LinearCalibration};
+use crate::config::{AlertConfig, Config, ConfigError, FieldTagConfig, MeasurementConfig};
+use crate::dedup::Deduplicator;
+use crate::derived::{
+    altitude_meters, clamp_humidity, compute_all, condensation_risk, derived_metrics,
+    heat_index_celsius, saturation_vapor_pressure_hpa, sea_level_pressure_hpa,
+    vapor_pressure_deficit_kpa, vapor_pressure_hpa, DerivedFlags, DerivedMetric,
+};
+use crate::events::{event_record, heartbeat_record, EventKind};
+use crate::http::{ReadRequest, SharedReading};
+use crate::iaq::{
+    eco2_ppm, gas_is_valid, humidity_compensated_gas, iaq_index, BaselineFile, GasBaseline,
+};
+use crate::jitter::Jitter;
+use crate::location::location_record;
+use crate::mqtt::MqttPublisher;
+use crate::reading::{
+    clock_is_plausible, unix_nanos, validate_reading, Reading, Record, ValidRanges,
+};
+use crate::retry::{retry, RetryPolicy};
+use crate::schedule::{minute_of_day, DailyWindow};
+use crate::sensor::{
+    blocking_io, read_averaged, EnvSensor, FailureCounter, GasStability, SensorError,
+};
+use crate::sink::{ReadingSink, SinkError};
+use crate::smoothing::{parse_smoothing, ReadingSmoother, Smoothing};
+use crate::stats::Stats;
+use crate::trend::{History, Trend};
+use crate::units::{
+    parse_gas_unit, parse_pressure_unit, parse_temperature_unit, round_half_even, GasUnit,
+    PressureUnit, TemperatureUnit,
+};
+use crate::zambretti::{forecast_text, is_summer, tendency, zambretti_code};
+use chrono::{Datelike, Local, TimeZone, Utc};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+use tracing::{debug, error, field, info, info_span, warn, Instrument, Span};
+
+/// Measurement names of the raw values, resolved once from the config and units.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeasurementNames {
+    pub temperature: String,
+    pub humidity: String,
+    pub pressure: String,
+    pub gas: String,
+}
+
+impl MeasurementNames {
+    pub fn resolve(
+        config: &MeasurementConfig,
+        temp_unit: TemperatureUnit,
+        pressure_unit: PressureUnit,
+        gas_unit: GasUnit,
+    ) -> Self {
+        MeasurementNames {
+            temperature: config
+                .temperature
+                .clone()
+                .unwrap_or_else(|| format!("temperature_{}", temp_unit.suffix())),
+            humidity: config
+                .humidity
+                .clone()
+                .unwrap_or_else(|| "relative_humidity".to_string()),
+            pressure: config
+                .pressure
+                .clone()
+                .unwrap_or_else(|| format!("pressure_{}", pressure_unit.suffix())),
+            gas: config
+                .gas
+                .clone()
+                .unwrap_or_else(|| gas_unit.measurement().to_string()),
+        }
+    }
+}
+
+/// Span of the pressure tendency the Zambretti forecast is based on.
+const ZAMBRETTI_SPAN: Duration = Duration::from_secs(3 * 60 * 60);
+
+/// Whether a reading can be trusted yet, written as the `quality` tag.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quality {
+    /// Taken within the warm-up after (re)initialization, or before the gas burn-in finished.
+    Warmup,
+    Stable,
+}
+
+impl Quality {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Quality::Warmup => "warmup",
+            Quality::Stable => "stable",
+        }
+    }
+}
+
+/// Quality of a reading taken `since_init` after the sensor was (re)initialized. `gas_burned_in`
+/// is `None` when gas isn't measured and so doesn't need to burn in.
+pub fn quality(since_init: Duration, warmup: Duration, gas_burned_in: Option<bool>) -> Quality {
+    if since_init >= warmup && gas_burned_in.unwrap_or(true) {
+        Quality::Stable
+    } else {
+        Quality::Warmup
+    }
+}
+
+/// Why a reading didn't make it to the sink. Both are logged when they happen.
+#[derive(Debug)]
+pub enum HandleError {
+    /// The reading was outside the valid ranges and discarded.
+    Implausible(String),
+    Write(SinkError),
+}
+
+impl fmt::Display for HandleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandleError::Implausible(e) => write!(f, "implausible reading: {}", e),
+            HandleError::Write(e) => write!(f, "write failed: {}", e),
+        }
+    }
+}
+
+/// State kept per sensor: its tags, smoothing window, gas baseline, trends, alert thresholds
+/// and the last written values.
+struct Channel {
+    label: Option<String>,
+    tags: BTreeMap<String, String>,
+    smoother: Option<ReadingSmoother>,
+    gas_baseline: GasBaseline,
+    gas_baseline_valid: bool,
+    /// Gas readings rejected as below `min_valid_gas_ohms`.
+    rejected_gas: u64,
+    pressure_trend: Trend,
+    temperature_trend: Trend,
+    /// Three hours of pressure for the Zambretti forecast.
+    pressure_history: History,
+    alerter: Option<Alerter>,
+    temperature_calibration: LinearCalibration,
+    humidity_calibration: LinearCalibration,
+    dedup: Option<Deduplicator>,
+    aggregator: Option<Aggregator>,
+    derived: Vec<Box<dyn DerivedMetric>>,
+    /// When the sensor was last (re)initialized, for the warm-up.
+    initialized: Instant,
+}
+
+/// Smooths readings, derives metrics from them and hands the results to the sink, MQTT, alerts
+/// and the metrics endpoint.
+///
+/// Each sensor gets its own channel of state. MQTT and the metrics endpoint follow the first
+/// sensor only.
+pub struct Collector {
+    sink: Box<dyn ReadingSink>,
+    mqtt: Option<MqttPublisher>,
+    latest: Option<SharedReading>,
+    channels: Vec<Channel>,
+    host: String,
+    tags: BTreeMap<String, String>,
+    smoothing: Option<Smoothing>,
+    dedup_max_interval: Option<Duration>,
+    dedup_epsilon: f64,
+    aggregate_window: Option<Duration>,
+    /// Where aggregates go instead of `sink`, when set.
+    aggregate_sink: Option<Box<dyn ReadingSink>>,
+    /// Aggregates of finished windows waiting to be written.
+    summaries: Vec<Record>,
+    gas_baseline_window: Duration,
+    gas_burn_in: Duration,
+    warmup: Duration,
+    iaq_heater_temp_c: u16,
+    /// Gas resistances below this are invalid and kept out of smoothing and the baseline.
+    min_valid_gas_ohms: Option<f64>,
+    /// Leave invalid gas resistances out of the record instead of writing them.
+    omit_invalid_gas: bool,
+    alert: AlertConfig,
+    temp_unit: TemperatureUnit,
+    pressure_unit: PressureUnit,
+    gas_unit: GasUnit,
+    names: MeasurementNames,
+    field_tags: FieldTagConfig,
+    station_altitude_m: f64,
+    leaf_temp_offset_c: f64,
+    surface_temp_offset_c: f64,
+    emit_psychrometrics: bool,
+    derived: DerivedFlags,
+    emit_compensated_gas: bool,
+    emit_eco2: bool,
+    write_events: bool,
+    write_heartbeat: bool,
+    zambretti_text: bool,
+    southern_hemisphere: bool,
+    sea_level_hpa: f64,
+    value_precision: Option<u32>,
+    field_precision: BTreeMap<String, u32>,
+    valid_ranges: ValidRanges,
+    /// Bounds relative humidity is clamped to for derived metrics.
+    humidity_clamp: Option<(f64, f64)>,
+    /// Offset the sensor adds itself during compensation, in °C.
+    sensor_temp_offset: f64,
+    /// Corrections for sensors without an entry in `calibrations`.
+    temperature_calibration: LinearCalibration,
+    humidity_calibration: LinearCalibration,
+    calibrations: CalibrationFile,
+    stats: Arc<Stats>,
+    baseline_store: Option<BaselineStore>,
+    clock_gate: Option<ClockGate>,
+    /// Time from the start of the previous cycle to the start of the current one.
+    cycle_duration: Option<Duration>,
+}
+
+/// Readings held back while the system clock looks unset, with the sensor index and when they
+/// were taken so they can be timestamped once it is.
+struct ClockGate {
+    deadline: Instant,
+    held: Vec<(usize, Instant, Reading)>,
+}
+
+/// Where and how often the gas baselines are saved.
+struct BaselineStore {
+    path: PathBuf,
+    interval: Duration,
+    saved: Instant,
+}
+
+/// Key of a channel's baseline in the state file.
+fn baseline_key(label: Option<&str>) -> String {
+    label.unwrap_or("default").to_string()
+}
+
+impl Collector {
+    pub fn new(config: &Config, sink: Box<dyn ReadingSink>) -> Result<Collector, ConfigError> {
+        let temp_unit = parse_temperature_unit(&config.temp_unit)
+            .map_err(|e| ConfigError(format!("Failed to load TEMP_UNIT: {}", e)))?;
+        let pressure_unit = parse_pressure_unit(&config.pressure_unit)
+            .map_err(|e| ConfigError(format!("Failed to load PRESSURE_UNIT: {}", e)))?;
+        let gas_unit = parse_gas_unit(&config.gas_unit)
+            .map_err(|e| ConfigError(format!("Failed to load GAS_UNIT: {}", e)))?;
+
+        let calibrations = match &config.calibration_path {
+            Some(path) => {
+                let file = CalibrationFile::load(Path::new(path))
+                    .map_err(|e| ConfigError(format!("Failed to load CALIBRATION_FILE: {}", e)))?;
+                info!(
+                    "Loaded calibration for {} sensors or hosts from {}.",
+                    file.entries.len(),
+                    path
+                );
+                file
+            }
+            None => CalibrationFile::default(),
+        };
+
+        let mut tags = config.tags.clone();
+        tags.insert("host".to_string(), config.host.clone());
+
+        let smoothing = parse_smoothing(
+            &config.smoothing_type,
+            config.smoothing_window,
+            config.smoothing_alpha,
+            config.window_secs,
+        )
+        .map_err(|e| ConfigError(format!("Failed to load SMOOTHING_TYPE: {}", e)))?;
+        match smoothing {
+            Some(Smoothing::MovingAverage(window)) => {
+                info!("Smoothing readings over a window of {} samples.", window)
+            }
+            Some(Smoothing::Exponential(alpha)) => info!(
+                "Smoothing readings with an exponential moving average, alpha {}.",
+                alpha
+            ),
+            Some(Smoothing::TimeWindow(window)) => {
+                info!("Averaging readings over the trailing {:?}.", window)
+            }
+            None => {}
+        }
+
+        let humidity_clamp = if config.clamp_humidity {
+            let bounds = (
+                config.humidity_clamp_min_percent,
+                config.humidity_clamp_max_percent,
+            );
+            if bounds.0 >= bounds.1 {
+                return Err(ConfigError(format!(
+                    "Failed to load HUMIDITY_CLAMP_MIN_PERCENT and HUMIDITY_CLAMP_MAX_PERCENT: \
+                     {} isn't below {}",
+                    bounds.0, bounds.1
+                )));
+            }
+            Some(bounds)
+        } else {
+            None
+        };
+
+        let aggregate_window = config.aggregate_window_secs.map(Duration::from_secs);
+        if let Some(window) = aggregate_window {
+            info!("Writing min, max and mean aggregates every {:?}.", window);
+        }
+
+        let clock_gate = match config.clock_wait_secs {
+            Some(secs) if !clock_is_plausible(SystemTime::now()) => {
+                let wait = Duration::from_secs(secs);
+                info!(
+                    "Waiting up to {:?} for a sane system clock, holding readings back until then.",
+                    wait
+                );
+                Some(ClockGate {
+                    deadline: Instant::now() + wait,
+                    held: Vec::new(),
+                })
+            }
+            _ => None,
+        };
+
+        let mut collector = Collector {
+            sink,
+            mqtt: None,
+            latest: None,
+            channels: Vec::new(),
+            host: config.host.clone(),
+            tags,
+            smoothing,
+            dedup_max_interval: if config.dedup {
+                Some(Duration::from_secs(config.dedup_max_interval_secs))
+            } else {
+                None
+            },
+            dedup_epsilon: config.dedup_epsilon,
+            aggregate_window,
+            aggregate_sink: None,
+            summaries: Vec::new(),
+            gas_baseline_window: Duration::from_secs(config.gas_baseline_window_secs),
+            gas_burn_in: Duration::from_secs(config.gas_burn_in_secs),
+            warmup: Duration::from_secs(config.warmup_secs),
+            iaq_heater_temp_c: config.sensor.gas_heater_temp_c,
+            min_valid_gas_ohms: config.min_valid_gas_ohms,
+            omit_invalid_gas: config.omit_invalid_gas,
+            alert: config.alert.clone(),
+            temp_unit,
+            pressure_unit,
+            gas_unit,
+            names: MeasurementNames::resolve(
+                &config.measurements,
+                temp_unit,
+                pressure_unit,
+                gas_unit,
+            ),
+            field_tags: config.field_tags.clone(),
+            station_altitude_m: config.station_altitude_m,
+            leaf_temp_offset_c: config.leaf_temp_offset_c,
+            surface_temp_offset_c: config.surface_temp_offset_c,
+            emit_psychrometrics: config.emit_psychrometrics,
+            derived: DerivedFlags {
+                dew_point: config.emit_dew_point,
+                absolute_humidity: config.emit_absolute_humidity,
+            },
+            emit_compensated_gas: config.emit_compensated_gas,
+            emit_eco2: config.emit_eco2,
+            write_events: config.write_events,
+            write_heartbeat: config.write_heartbeat,
+            zambretti_text: config.zambretti_text,
+            southern_hemisphere: config.latitude.is_some_and(|latitude| latitude < 0.0),
+            sea_level_hpa: config.sea_level_hpa,
+            value_precision: config.value_precision,
+            field_precision: config.field_precision.clone(),
+            valid_ranges: config.valid_ranges,
+            humidity_clamp,
+            sensor_temp_offset: config.sensor.temperature_offset as f64,
+            temperature_calibration: LinearCalibration::new(
+                config.sensor.temperature_gain,
+                config.sensor.temperature_offset as f64,
+            ),
+            humidity_calibration: LinearCalibration::new(
+                config.sensor.humidity_gain,
+                config.sensor.humidity_offset,
+            ),
+            calibrations,
+            stats: Arc::new(Stats::default()),
+            baseline_store: None,
+            clock_gate,
+            cycle_duration: None,
+        };
+        collector.channels.push(collector.channel(None));
+        Ok(collector)
+    }
+
+    fn channel(&self, label: Option<&str>) -> Channel {
+        let mut tags = self.tags.clone();
+        if let Some(label) = label {
+            tags.insert("sensor".to_string(), label.to_string());
+        }
+        let (temperature_calibration, humidity_calibration) =
+            match self.calibrations.lookup(label, &self.host) {
+                Some(entry) => (entry.temperature(), entry.humidity()),
+                None => (self.temperature_calibration, self.humidity_calibration),
+            };
+        Channel {
+            label: label.map(str::to_string),
+            tags,
+            smoother: self.smoothing.map(ReadingSmoother::new),
+            gas_baseline: GasBaseline::new(self.gas_baseline_window, self.gas_burn_in),
+            gas_baseline_valid: false,
+            rejected_gas: 0,
+            pressure_trend: Trend::new(),
+            temperature_trend: Trend::new(),
+            pressure_history: History::new(ZAMBRETTI_SPAN),
+            alerter: Alerter::from_config(&self.alert, &self.host, label),
+            temperature_calibration,
+            humidity_calibration,
+            dedup: self
+                .dedup_max_interval
+                .map(|max_interval| Deduplicator::new(max_interval, self.dedup_epsilon)),
+            aggregator: self.aggregate_window.map(Aggregator::new),
+            derived: derived_metrics(self.derived, self.temp_unit),
+            initialized: Instant::now(),
+        }
+    }
+
+    /// Collect from several sensors, one per label, tagging their readings with `sensor`.
+    /// Readings are then handled by the index of their label.
+    pub fn with_sensors(mut self, labels: &[String]) -> Self {
+        if !labels.is_empty() {
+            self.channels = labels
+                .iter()
+                .map(|label| self.channel(Some(label.as_str())))
+                .collect();
+        }
+        self
+    }
+
+    /// Also publish every reading over MQTT.
+    pub fn with_mqtt(mut self, mqtt: MqttPublisher) -> Self {
+        self.mqtt = Some(mqtt);
+        self
+    }
+
+    /// Keep the latest reading in `latest` for the metrics endpoint.
+    pub fn with_latest(mut self, latest: SharedReading) -> Self {
+        self.latest = Some(latest);
+        self
+    }
+
+    /// Write the aggregates to `sink` instead of the sink the readings go to.
+    pub fn with_aggregate_sink(mut self, sink: Box<dyn ReadingSink>) -> Self {
+        self.aggregate_sink = Some(sink);
+        self
+    }
+
+    /// Save the gas baselines to `path` every `interval` and on shutdown, first restoring them
+    /// from there unless the saved state is older than `max_age`. A missing, unreadable or stale
+    /// file means starting over with a warning.
+    pub fn with_baseline_state(
+        mut self,
+        path: PathBuf,
+        interval: Duration,
+        max_age: Duration,
+    ) -> Self {
+        match BaselineFile::load(&path) {
+            Ok(Some(file)) => {
+                let age = file.age(SystemTime::now());
+                if age > max_age {
+                    warn!(
+                        "Gas baseline state in {} is {:?} old, older than {:?}, starting fresh.",
+                        path.display(),
+                        age,
+                        max_age
+                    );
+                } else {
+                    let now = Instant::now();
+                    for channel in self.channels.iter_mut() {
+                        let key = baseline_key(channel.label.as_deref());
+                        if let Some(state) = file.channels.get(&key) {
+                            channel.gas_baseline.restore(now, state, age);
+                            info!(
+                                "Restored gas baseline{} from {}.",
+                                label_suffix(channel.label.as_deref()),
+                                path.display()
+                            );
+                        }
+                    }
+                }
+            }
+            Ok(None) => warn!(
+                "No gas baseline state in {} yet, starting fresh.",
+                path.display()
+            ),
+            Err(e) => warn!("Ignoring gas baseline state, starting fresh: {}", e),
+        }
+        self.baseline_store = Some(BaselineStore {
+            path,
+            interval,
+            saved: Instant::now(),
+        });
+        self
+    }
+
+    /// Write the gas baselines out, when they are persisted at all.
+    fn save_baselines(&mut self) {
+        let store = match self.baseline_store.as_mut() {
+            Some(store) => store,
+            None => return,
+        };
+        let now = Instant::now();
+        let file = BaselineFile::new(
+            self.channels
+                .iter()
+                .map(|channel| {
+                    (
+                        baseline_key(channel.label.as_deref()),
+                        channel.gas_baseline.state(now),
+                    )
+                })
+                .collect(),
+        );
+        if let Err(e) = file.save(&store.path) {
+            warn!("Failed to save gas baselines: {}", e);
+        }
+        store.saved = now;
+    }
+
+    /// Counters about the loop itself, shared with the metrics endpoint.
+    pub fn stats(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    pub fn sink(&self) -> &dyn ReadingSink {
+        self.sink.as_ref()
+    }
+
+    /// Calibrate and smooth a raw reading from the sensor at index `channel`, then write it and
+    /// everything derived from it. Implausible readings are dropped before they reach the
+    /// smoother.
+    ///
+    /// While waiting for a sane system clock, readings are held back instead, and handled with
+    /// corrected timestamps once the clock looks set or the wait is over.
+    pub async fn handle(&mut self, channel: usize, reading: Reading) -> Result<(), HandleError> {
+        if let Some(gate) = self.clock_gate.as_mut() {
+            let sane = clock_is_plausible(SystemTime::now());
+            if !sane && Instant::now() < gate.deadline {
+                gate.held.push((channel, Instant::now(), reading));
+                return Ok(());
+            }
+            self.release_held(sane).await;
+        }
+        self.process(channel, reading).await
+    }
+
+    /// Handle the readings held back for the clock and stop holding them. With `restamp`, their
+    /// timestamps are recomputed from the now sane clock.
+    async fn release_held(&mut self, restamp: bool) {
+        let gate = match self.clock_gate.take() {
+            Some(gate) => gate,
+            None => return,
+        };
+        if restamp {
+            info!(
+                "System clock looks sane, writing {} held readings.",
+                gate.held.len()
+            );
+        } else {
+            warn!(
+                "System clock still looks unset, writing {} held readings with its timestamps.",
+                gate.held.len()
+            );
+        }
+        let now = SystemTime::now();
+        for (channel, taken, mut reading) in gate.held {
+            if restamp {
+                reading.timestamp = unix_nanos(now.checked_sub(taken.elapsed()).unwrap_or(now));
+            }
+            let _ = self.process(channel, reading).await;
+        }
+    }
+
+    async fn process(&mut self, channel: usize, mut reading: Reading) -> Result<(), HandleError> {
+        self.calibrate(channel, &mut reading);
+        if let Err(e) = validate_reading(&reading, &self.valid_ranges) {
+            warn!("Discarding implausible reading: {}", e);
+            self.stats.record_read_error();
+            return Err(HandleError::Implausible(e));
+        }
+        let state = match self.channels.get_mut(channel) {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+        let invalid_gas = match reading.gas_ohms {
+            Some(gas_ohms) if !gas_is_valid(gas_ohms, self.min_valid_gas_ohms) => {
+                state.rejected_gas += 1;
+                warn!(
+                    "Rejected gas resistance of {} ohms{}, below MIN_VALID_GAS_OHMS ({} so far).",
+                    gas_ohms,
+                    label_suffix(state.label.as_deref()),
+                    state.rejected_gas
+                );
+                reading.gas_ohms.take()
+            }
+            _ => None,
+        };
+        if let Some(smoother) = state.smoother.as_mut() {
+            smoother.apply(&mut reading);
+        }
+        if !self.omit_invalid_gas {
+            reading.gas_ohms = reading.gas_ohms.or(invalid_gas);
+        }
+        let alerts = match state.alerter.as_mut() {
+            Some(alerter) => alerter.check(&reading),
+            None => Vec::new(),
+        };
+        for alert in alerts {
+            self.write_event(Some(channel), EventKind::Alert, &alert.to_string())
+                .await;
+        }
+        if channel == 0 {
+            if let Some(latest) = &self.latest {
+                *latest.write().await = Some(reading);
+            }
+            if let Some(mqtt) = &self.mqtt {
+                if let Err(e) = mqtt.publish_reading(&reading) {
+                    error!("Failed to publish reading to MQTT: {:?}", e);
+                }
+            }
+        }
+
+        let record = self.record(channel, &reading);
+        let baseline_due = self
+            .baseline_store
+            .as_ref()
+            .is_some_and(|store| store.saved.elapsed() >= store.interval);
+        if baseline_due {
+            self.save_baselines();
+        }
+        self.write_summaries().await;
+        if record.fields.is_empty() {
+            return Ok(());
+        }
+        let started = Instant::now();
+        let result = self.sink.write_reading(&record).await;
+        Span::current().record("write_ms", millis(started.elapsed()));
+        if let Err(e) = &result {
+            error!("Failed to write reading to {}: {}", self.sink.name(), e);
+        }
+        self.stats.record_write(result.is_ok());
+        self.stats.set_buffered_points(self.sink.buffered());
+        result.map_err(HandleError::Write)
+    }
+
+    /// Start the smoothing, the trends and the warm-up of the sensor at index `channel` over,
+    /// after it was re-initialized.
+    pub fn reset_after_reinit(&mut self, channel: usize) {
+        let smoothing = self.smoothing;
+        if let Some(state) = self.channels.get_mut(channel) {
+            state.smoother = smoothing.map(ReadingSmoother::new);
+            state.initialized = Instant::now();
+            state.pressure_trend.reset();
+            state.temperature_trend.reset();
+            state.pressure_history.reset();
+        }
+    }
+
+    /// Apply the corrections of the sensor at index `channel`. The sensor already adds the
+    /// configured temperature offset during compensation, so it is taken out again before the
+    /// correction is applied to the raw value.
+    fn calibrate(&self, channel: usize, reading: &mut Reading) {
+        let state = match self.channels.get(channel) {
+            Some(state) => state,
+            None => return,
+        };
+        let temperature = state.temperature_calibration;
+        if temperature != LinearCalibration::new(1.0, self.sensor_temp_offset) {
+            reading.temperature_c =
+                temperature.apply(reading.temperature_c - self.sensor_temp_offset);
+        }
+        if !state.humidity_calibration.is_identity() {
+            reading.humidity_percent = state.humidity_calibration.apply(reading.humidity_percent);
+        }
+    }
+
+    fn record(&mut self, channel: usize, reading: &Reading) -> Record {
+        let Reading {
+            timestamp,
+            temperature_c,
+            humidity_percent,
+            pressure_hpa,
+            gas_ohms,
+            heater_temp_c,
+        } = *reading;
+        let temp_unit = self.temp_unit;
+        let pressure_unit = self.pressure_unit;
+
+        let state = &mut self.channels[channel];
+        let mut record = Record::new(timestamp, state.tags.clone());
+        let names = &self.names;
+        let field_tags = &self.field_tags;
+        record.push_tagged(
+            &names.temperature,
+            reading.temperature_in(temp_unit),
+            field_tags.temperature.clone(),
+        );
+        record.push_tagged(
+            &names.humidity,
+            humidity_percent,
+            field_tags.humidity.clone(),
+        );
+        // The raw humidity is written as is, only what is derived from it is clamped.
+        let humidity_percent = match self.humidity_clamp {
+            Some((min, max)) => {
+                let clamped = clamp_humidity(humidity_percent, min, max);
+                if clamped != humidity_percent {
+                    debug!(
+                        "Clamped humidity of {}% to {}% for derived metrics.",
+                        humidity_percent, clamped
+                    );
+                }
+                clamped
+            }
+            None => humidity_percent,
+        };
+        record.push_tagged(
+            &names.pressure,
+            reading.pressure_in(pressure_unit),
+            field_tags.pressure.clone(),
+        );
+        if let Some(gas) = reading.gas_in(self.gas_unit) {
+            let mut gas_tags = field_tags.gas.clone();
+            if let Some(temp) = heater_temp_c {
+                gas_tags.insert("heater_temp_c".to_string(), temp.to_string());
+            }
+            if self.emit_compensated_gas {
+                if let Some(gas_ohms) = gas_ohms {
+                    record.push_tagged(
+                        "gas_resistance_compensated_ohm",
+                        humidity_compensated_gas(gas_ohms, humidity_percent),
+                        gas_tags.clone(),
+                    );
+                }
+            }
+            record.push_tagged(&names.gas, gas, gas_tags);
+        }
+        record.fields.extend(compute_all(
+            &mut state.derived,
+            &Reading {
+                humidity_percent,
+                ..*reading
+            },
+        ));
+        record.push(
+            &format!("heat_index_{}", temp_unit.suffix()),
+            temp_unit.convert(heat_index_celsius(temperature_c, humidity_percent)),
+        );
+        if self.emit_psychrometrics {
+            record.push(
+                "saturation_vapor_pressure_hpa",
+                saturation_vapor_pressure_hpa(temperature_c),
+            );
+            record.push(
+                "vapor_pressure_hpa",
+                vapor_pressure_hpa(temperature_c, humidity_percent),
+            );
+        }
+        let qnh_hpa = sea_level_pressure_hpa(pressure_hpa, self.station_altitude_m, temperature_c);
+        record.push(
+            &format!("sea_level_pressure_{}", pressure_unit.suffix()),
+            pressure_unit.convert(qnh_hpa),
+        );
+        if let Some(trend) = state.pressure_trend.update(timestamp, pressure_hpa) {
+            record.push(
+                &format!("pressure_trend_{}_per_hour", pressure_unit.suffix()),
+                pressure_unit.convert(trend),
+            );
+        }
+        if let Some(trend) = state.temperature_trend.update(timestamp, temperature_c) {
+            record.push(
+                &format!("temperature_trend_{}_per_hour", temp_unit.suffix()),
+                temp_unit.convert_delta(trend),
+            );
+        }
+        if let Some(change) = state.pressure_history.update(timestamp, pressure_hpa) {
+            let month = Utc.timestamp_nanos(timestamp).month();
+            let summer = is_summer(month, self.southern_hemisphere);
+            let code = zambretti_code(qnh_hpa, tendency(change), summer);
+            let mut tags = BTreeMap::new();
+            if self.zambretti_text {
+                tags.insert("forecast".to_string(), forecast_text(code).to_string());
+            }
+            record.push_tagged("zambretti_code", code as f64, tags);
+        }
+        record.push(
+            "vpd_kpa",
+            vapor_pressure_deficit_kpa(temperature_c, humidity_percent, self.leaf_temp_offset_c),
+        );
+        let condensation =
+            condensation_risk(temperature_c, humidity_percent, self.surface_temp_offset_c);
+        record.push("condensation_risk", if condensation { 1.0 } else { 0.0 });
+
+        let (iaq_heater_temp_c, min_valid_gas_ohms) =
+            (self.iaq_heater_temp_c, self.min_valid_gas_ohms);
+        let iaq_gas_ohms = gas_ohms
+            .filter(|_| heater_temp_c.is_none_or(|t| t == iaq_heater_temp_c))
+            .filter(|&gas_ohms| gas_is_valid(gas_ohms, min_valid_gas_ohms));
+        if let Some(gas_ohms) = iaq_gas_ohms {
+            let now = Instant::now();
+            // The baseline tracks compensated resistances, so it compares across the humidity
+            // swings of a day.
+            let compensated_ohms = humidity_compensated_gas(gas_ohms, humidity_percent);
+            let baseline_ohms = state.gas_baseline.update(now, compensated_ohms);
+            if state.gas_baseline.is_burned_in(now) {
+                if !state.gas_baseline_valid {
+                    info!(
+                        "Gas baseline burn-in complete{} at {:.0} ohms.",
+                        label_suffix(state.label.as_deref()),
+                        baseline_ohms
+                    );
+                    state.gas_baseline_valid = true;
+                }
+                record.push("iaq", iaq_index(gas_ohms, humidity_percent, baseline_ohms));
+                if self.emit_eco2 {
+                    record.push(
+                        "eco2_ppm",
+                        eco2_ppm(gas_ohms, baseline_ohms, humidity_percent),
+                    );
+                }
+            }
+        }
+
+        let altitude = altitude_meters(pressure_hpa, self.sea_level_hpa);
+        if altitude.is_finite() {
+            record.push("altitude_m", altitude);
+        }
+
+        let now = Instant::now();
+        let since_init = now.saturating_duration_since(state.initialized);
+        // Drops back to zero whenever the sensor is re-initialized, marking recoveries.
+        record.push("sensor_uptime_secs", since_init.as_secs() as f64);
+        let gas_burned_in = gas_ohms.map(|_| state.gas_baseline.is_burned_in(now));
+        let quality = quality(since_init, self.warmup, gas_burned_in);
+        record
+            .tags
+            .insert("quality".to_string(), quality.as_str().to_string());
+
+        if let Some(aggregator) = state.aggregator.as_mut() {
+            if let Some(summary) = aggregator.push(&record, &state.tags) {
+                self.summaries.push(summary);
+            }
+        }
+
+        for field in record.fields.iter_mut() {
+            let places = self
+                .field_precision
+                .get(&field.measurement)
+                .copied()
+                .or(self.value_precision);
+            if let Some(places) = places {
+                field.value = round_half_even(field.value, places);
+            }
+        }
+
+        let stats = self.stats.snapshot();
+        record.push("atmosphere_reads_total", stats.reads_total as f64);
+        record.push(
+            "atmosphere_read_errors_total",
+            stats.read_errors_total as f64,
+        );
+        record.push("atmosphere_writes_total", stats.writes_total as f64);
+        record.push("atmosphere_buffered_points", stats.buffered_points as f64);
+        if let Some(cycle_duration) = self.cycle_duration {
+            record.push("cycle_duration_secs", cycle_duration.as_secs_f64());
+        }
+
+        if let Some(dedup) = state.dedup.as_mut() {
+            // Only the raw values sit still for long, what is derived from them moves with
+            // every reading.
+            let raw = [&names.temperature, &names.humidity, &names.pressure];
+            record.fields.retain(|field| {
+                !raw.contains(&&field.measurement)
+                    || dedup.should_write(&field.measurement, field.value, timestamp)
+            });
+        }
+        record
+    }
+
+    /// Write an event for the sensor at index `channel`, or the host when `None`, if events are
+    /// written at all. Failures are only logged.
+    pub async fn write_event(&mut self, channel: Option<usize>, kind: EventKind, message: &str) {
+        if !self.write_events {
+            return;
+        }
+        let tags = match channel.and_then(|channel| self.channels.get(channel)) {
+            Some(state) => &state.tags,
+            None => &self.tags,
+        };
+        let record = event_record(kind, message, unix_nanos(SystemTime::now()), tags);
+        if let Err(e) = self.sink.write_reading(&record).await {
+            error!(
+                "Failed to write {} event to {}: {}",
+                kind.as_str(),
+                self.sink.name(),
+                e
+            );
+        }
+    }
+
+    /// Write the heartbeat of a cycle, tagged like the host, if heartbeats are written at all.
+    /// Failures are only logged.
+    pub async fn write_heartbeat(&mut self, has_new_data: bool) {
+        if !self.write_heartbeat {
+            return;
+        }
+        let record = heartbeat_record(has_new_data, unix_nanos(SystemTime::now()), &self.tags);
+        if let Err(e) = self.sink.write_reading(&record).await {
+            error!("Failed to write heartbeat to {}: {}", self.sink.name(), e);
+        }
+    }
+
+    /// Write the station's coordinates, tagged like the host. Failures are only logged.
+    pub async fn write_location(&mut self, latitude: f64, longitude: f64) {
+        let record = location_record(
+            latitude,
+            longitude,
+            unix_nanos(SystemTime::now()),
+            &self.tags,
+        );
+        if let Err(e) = self.sink.write_reading(&record).await {
+            error!("Failed to write location to {}: {}", self.sink.name(), e);
+        }
+    }
+
+    /// Write one record of settings, e.g. calibration metadata, tagged like the readings of
+    /// every sensor.
+    pub async fn write_metadata(&mut self, fields: &[(&str, f64)]) {
+        let timestamp = unix_nanos(SystemTime::now());
+        for channel in &self.channels {
+            let mut record = Record::new(timestamp, channel.tags.clone());
+            for (measurement, value) in fields {
+                record.push(measurement, *value);
+            }
+            if let Err(e) = self.sink.write_reading(&record).await {
+                error!("Failed to write metadata to {}: {}", self.sink.name(), e);
+            }
+        }
+    }
+
+    /// Write the aggregates of finished windows.
+    async fn write_summaries(&mut self) {
+        for summary in std::mem::take(&mut self.summaries) {
+            let sink = self.aggregate_sink.as_mut().unwrap_or(&mut self.sink);
+            if let Err(e) = sink.write_reading(&summary).await {
+                error!("Failed to write aggregates to {}: {}", sink.name(), e);
+            }
+        }
+    }
+
+    /// Save the gas baselines, write the partial aggregate windows and write out whatever the
+    /// sinks are holding back.
+    pub async fn flush(&mut self) -> Result<(), SinkError> {
+        self.release_held(clock_is_plausible(SystemTime::now()))
+            .await;
+        self.save_baselines();
+        for channel in self.channels.iter_mut() {
+            if let Some(aggregator) = channel.aggregator.as_mut() {
+                if let Some(summary) = aggregator.take(&channel.tags) {
+                    self.summaries.push(summary);
+                }
+            }
+        }
+        self.write_summaries().await;
+        if let Some(sink) = self.aggregate_sink.as_mut() {
+            if let Err(e) = sink.flush().await {
+                error!("Failed to flush aggregates to {}: {}", sink.name(), e);
+            }
+        }
+        info!(
+            "Shutting down, flushing {} buffered points.",
+            self.sink.buffered()
+        );
+        self.sink.flush().await
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// ` for <label>` for log lines about a labeled sensor, empty otherwise.
+fn label_suffix(label: Option<&str>) -> String {
+    match label {
+        Some(label) => format!(" for {}", label),
+        None => String::new(),
+    }
+}
+
+/// Poll the first sensor every `poll` without writing until its gas resistance at
+/// `heater_temp_c` is stable by `stability`, or for at most `max_wait`. Returns whether it
+/// settled. Failed reads are skipped.
+pub async fn wait_for_gas_stability(
+    sensors: &mut [Box<dyn EnvSensor>],
+    mut stability: GasStability,
+    heater_temp_c: u16,
+    poll: Duration,
+    max_wait: Duration,
+) -> bool {
+    let sensor = match sensors.first_mut() {
+        Some(sensor) => sensor,
+        None => return false,
+    };
+    let started = Instant::now();
+    while started.elapsed() < max_wait {
+        match blocking_io(|| sensor.read()) {
+            Ok(reading) => {
+                let gas_ohms = reading
+                    .gas_ohms
+                    .filter(|_| reading.heater_temp_c.is_none_or(|t| t == heater_temp_c));
+                if let Some(gas_ohms) = gas_ohms {
+                    debug!("Gas resistance while stabilizing: {:.0} ohms.", gas_ohms);
+                    if stability.push(Instant::now(), gas_ohms) {
+                        return true;
+                    }
+                }
+            }
+            Err(e) => debug!("Read while stabilizing failed: {}", e),
+        }
+        sleep(poll).await;
+    }
+    false
+}
+
+/// Read every sensor once and check the readings are plausible, so a mis-wired or misconfigured
+/// sensor shows up right away instead of after the stabilization wait. The readings are logged,
+/// and only handled like regular ones when `write` is set.
+pub async fn self_test(
+    sensors: &mut [Box<dyn EnvSensor>],
+    collector: &mut Collector,
+    write: bool,
+) -> Result<(), String> {
+    for (index, sensor) in sensors.iter_mut().enumerate() {
+        let mut reading =
+            blocking_io(|| sensor.read()).map_err(|e| format!("sensor {}: {}", index, e))?;
+        let raw = reading;
+        collector.calibrate(index, &mut reading);
+        validate_reading(&reading, &collector.valid_ranges)
+            .map_err(|e| format!("sensor {}: implausible reading, {}", index, e))?;
+        info!(
+            "Sensor {} self-test passed: {:.2} C, {:.2} %, {:.2} hPa{}.",
+            index,
+            reading.temperature_c,
+            reading.humidity_percent,
+            reading.pressure_hpa,
+            reading
+                .gas_ohms
+                .map(|gas_ohms| format!(", {:.0} ohms", gas_ohms))
+                .unwrap_or_default()
+        );
+        if write {
+            collector.stats.record_read();
+            collector
+                .handle(index, raw)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+/// Why a single `--once` collection failed, told apart by the exit code.
+#[derive(Debug)]
+pub enum OnceError {
+    Read(String),
+    Write(String),
+}
+
+impl OnceError {
+    /// 2 for read failures, 3 for write failures. 1 is left for setup errors.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            OnceError::Read(_) => 2,
+            OnceError::Write(_) => 3,
+        }
+    }
+}
+
+impl fmt::Display for OnceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OnceError::Read(e) => write!(f, "read failed: {}", e),
+            OnceError::Write(e) => write!(f, "write failed: {}", e),
+        }
+    }
+}
+
+/// Take one reading from every sensor, write them and flush the sink, for runs driven by an
+/// external scheduler. A sensor without new data is read again after `retry_after`, once.
+pub async fn run_once(
+    sensors: &mut [Box<dyn EnvSensor>],
+    collector: &mut Collector,
+    retry_after: Duration,
+) -> Result<(), OnceError> {
+    for (index, sensor) in sensors.iter_mut().enumerate() {
+        let reading = match blocking_io(|| sensor.read()) {
+            Err(SensorError::NoNewData) => {
+                sleep(retry_after).await;
+                blocking_io(|| sensor.read())
+            }
+            read => read,
+        }
+        .map_err(|e| OnceError::Read(format!("sensor {}: {}", index, e)))?;
+        collector.stats.record_read();
+        match collector.handle(index, reading).await {
+            Ok(()) => {}
+            Err(HandleError::Implausible(e)) => {
+                return Err(OnceError::Read(format!("sensor {}: {}", index, e)))
+            }
+            Err(HandleError::Write(e)) => return Err(OnceError::Write(e.to_string())),
+        }
+    }
+    collector
+        .flush()
+        .await
+        .map_err(|e| OnceError::Write(e.to_string()))
+}
+
+/// How the read loop paces itself and recovers from sensor failures.
+#[derive(Debug, Clone, Copy)]
+pub struct LoopSettings {
+    pub read_interval: Duration,
+    /// Consecutive read failures before the sensor is re-initialized.
+    pub max_consecutive_read_errors: u32,
+    pub reinit_retry: RetryPolicy,
+    /// Reads averaged into each written reading.
+    pub samples_per_point: usize,
+    /// Stop after the first cycle that ends this long after the loop started.
+    pub max_runtime: Option<Duration>,
+    /// Local time during which gas measurement is off.
+    pub quiet_hours: Option<DailyWindow>,
+    /// Longest random delay added to the wait for each cycle, spreading the writes of nodes
+    /// that started together.
+    pub write_jitter: Option<Duration>,
+}
+
+/// How long to wait for the next cycle after one that took `elapsed`, so cycles start every
+/// `interval`. Zero once a cycle overran, rather than falling further behind.
+pub fn remaining_interval(interval: Duration, elapsed: Duration) -> Duration {
+    interval.saturating_sub(elapsed)
+}
+
+/// Whether a loop started at `started` has run for `max_runtime` by `now`. Never true without a
+/// limit.
+pub fn runtime_exceeded(started: Instant, now: Instant, max_runtime: Option<Duration>) -> bool {
+    max_runtime.is_some_and(|max_runtime| now.saturating_duration_since(started) >= max_runtime)
+}
+
+/// Read every sensor each `read_interval` until `shutdown` resolves. Each sensor's readings are
+/// handled by the collector channel at the same index.
+///
+/// Read failures are retried on the next cycle. After `max_consecutive_read_errors` of them a
+/// sensor is re-initialized, and dropped if that fails too while the others carry on. The loop
+/// gives up with the last error once no sensor is left.
+///
+/// With a `max_runtime` the loop also ends once that has passed, like on shutdown. During
+/// `quiet_hours` the sensors are read without gas measurement.
+///
+/// Reads through [`blocking_io`] and so panics outside a multi-threaded runtime.
+pub async fn run<F>(
+    sensors: &mut [Box<dyn EnvSensor>],
+    collector: &mut Collector,
+    settings: LoopSettings,
+    mut read_requests: Option<mpsc::Receiver<ReadRequest>>,
+    shutdown: F,
+) -> Result<(), SensorError>
+where
+    F: Future<Output = ()>,
+{
+    tokio::pin!(shutdown);
+    let mut read_failures: Vec<FailureCounter> = sensors
+        .iter()
+        .map(|_| FailureCounter::new(settings.max_consecutive_read_errors))
+        .collect();
+    let mut active = vec![true; sensors.len()];
+    // Sensors start out, and come back from a re-init, with gas measurement as configured.
+    let mut gas_paused = vec![false; sensors.len()];
+    let mut jitter = settings.write_jitter.map(Jitter::from_entropy);
+    let started = Instant::now();
+    let mut last_cycle: Option<Instant> = None;
+    loop {
+        let cycle_started = Instant::now();
+        collector.cycle_duration = last_cycle.map(|last| cycle_started.duration_since(last));
+        last_cycle = Some(cycle_started);
+        let quiet = settings
+            .quiet_hours
+            .is_some_and(|window| window.contains(minute_of_day(&Local::now())));
+        let mut has_new_data = false;
+        for (index, sensor) in sensors.iter_mut().enumerate() {
+            if !active[index] {
+                continue;
+            }
+            if gas_paused[index] != quiet {
+                // Failing to switch is retried next cycle, reading carries on either way.
+                match blocking_io(|| sensor.set_gas_enabled(!quiet)) {
+                    Ok(()) => {
+                        info!(
+                            "{} gas measurement on sensor {} for QUIET_HOURS.",
+                            if quiet { "Paused" } else { "Resumed" },
+                            index
+                        );
+                        gas_paused[index] = quiet;
+                    }
+                    Err(e) => warn!("Sensor {}: {}", index, e),
+                }
+            }
+            let failures = &mut read_failures[index];
+            let span = info_span!(
+                "cycle",
+                sensor = index,
+                read_ms = field::Empty,
+                write_ms = field::Empty,
+                temperature_c = field::Empty,
+                humidity_percent = field::Empty,
+                pressure_hpa = field::Empty,
+                gas_ohms = field::Empty,
+            );
+            let started = Instant::now();
+            let read = read_averaged(sensor.as_mut(), settings.samples_per_point)
+                .instrument(span.clone())
+                .await;
+            span.record("read_ms", millis(started.elapsed()));
+            match read {
+                Ok(reading) => {
+                    span.record("temperature_c", reading.temperature_c);
+                    span.record("humidity_percent", reading.humidity_percent);
+                    span.record("pressure_hpa", reading.pressure_hpa);
+                    if let Some(gas_ohms) = reading.gas_ohms {
+                        span.record("gas_ohms", gas_ohms);
+                    }
+                    failures.record_success();
+                    has_new_data = true;
+                    collector.stats.record_read();
+                    // Failures are logged and counted by the collector, the next cycle carries on.
+                    let _ = collector.handle(index, reading).instrument(span).await;
+                }
+                Err(SensorError::NoNewData) => failures.record_success(),
+                Err(e) => {
+                    span.in_scope(|| error!("Sensor {}: {}", index, e));
+                    collector.stats.record_read_error();
+                    if !failures.record_failure() {
+                        warn!(
+                            "Sensor {} read failed ({} consecutive), retrying next cycle.",
+                            index,
+                            failures.consecutive()
+                        );
+                        continue;
+                    }
+                    warn!(
+                        "{} consecutive read failures on sensor {}, re-initializing it.",
+                        failures.consecutive(),
+                        index
+                    );
+                    let reinit = retry(&settings.reinit_retry, "Sensor re-init", || {
+                        let result = blocking_io(|| sensor.reinit());
+                        async move { result }
+                    })
+                    .instrument(span)
+                    .await;
+                    match reinit {
+                        Ok(()) => {
+                            info!("Sensor {} re-initialized after read failures.", index);
+                            let message = format!(
+                                "Sensor {} re-initialized after {} consecutive read failures",
+                                index,
+                                failures.consecutive()
+                            );
+                            failures.record_success();
+                            gas_paused[index] = false;
+                            collector.reset_after_reinit(index);
+                            collector
+                                .write_event(Some(index), EventKind::Reinit, &message)
+                                .await;
+                        }
+                        Err(e) => {
+                            error!("Giving up on sensor {}: {}", index, e);
+                            active[index] = false;
+                            if !active.contains(&true) {
+                                return Err(e);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        collector.write_heartbeat(has_new_data).await;
+        if runtime_exceeded(started, Instant::now(), settings.max_runtime) {
+            info!(
+                "Reached MAX_RUNTIME_SECS after {:?}, exiting for a restart.",
+                started.elapsed()
+            );
+            return Ok(());
+        }
+        let elapsed = cycle_started.elapsed();
+        if elapsed >= settings.read_interval {
+            warn!(
+                "Cycle took {:?}, longer than the read interval of {:?}, starting the next one \
+                 right away.",
+                elapsed, settings.read_interval
+            );
+        }
+        // On-demand reads are answered while waiting, without moving the next cycle.
+        let jitter_delay = jitter
+            .as_mut()
+            .map_or(Duration::from_secs(0), Jitter::next_delay);
+        let interval = sleep(remaining_interval(settings.read_interval, elapsed) + jitter_delay);
+        tokio::pin!(interval);
+        loop {
+            let request = async {
+                match read_requests.as_mut() {
+                    Some(requests) => requests.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = &mut interval => break,
+                Some(reply) = request => read_on_demand(sensors, collector, reply),
+                _ = &mut shutdown => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Answer an on-demand read from the first sensor. The reading is calibrated but neither
+/// smoothed nor written, so the regular readings are unaffected.
+fn read_on_demand(sensors: &mut [Box<dyn EnvSensor>], collector: &Collector, reply: ReadRequest) {
+    let result = match sensors.first_mut() {
+        Some(sensor) => blocking_io(|| sensor.read())
+            .map(|mut reading| {
+                collector.calibrate(0, &mut reading);
+                reading
+            })
+            .map_err(|e| e.to_string()),
+        None => Err("no sensor configured".to_string()),
+    };
+    let _ = reply.send(result);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::derived::dew_point_celsius;
+    use crate::events::{EVENTS_MEASUREMENT, HEARTBEAT_MEASUREMENT};
+    use crate::sensor::MockSensor;
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    /// Keeps every written record for the test to look at.
+    #[derive(Clone, Default)]
+    struct RecordingSink {
+        records: Arc<Mutex<Vec<Record>>>,
+    }
+
+    #[async_trait]
+    impl ReadingSink for RecordingSink {
+        fn name(&self) -> &str {
+            "recording"
+        }
+
+        async fn write_reading(&mut self, record: &Record) -> Result<(), SinkError> {
+            self.records.lock().unwrap().push(record.clone());
+            Ok(())
+        }
+    }
+
+    /// Fails every write, like a sink whose server is down.
+    struct FailingSink;
+
+    #[async_trait]
+    impl ReadingSink for FailingSink {
+        fn name(&self) -> &str {
+            "failing"
+        }
+
+        async fn write_reading(&mut self, _: &Record) -> Result<(), SinkError> {
+            Err(SinkError("connection refused".into()))
+        }
+    }
+
+    fn reading(temperature_c: f64) -> Reading {
+        Reading {
+            timestamp: unix_nanos(SystemTime::now()),
+            temperature_c,
+            humidity_percent: 45.0,
+            pressure_hpa: 1013.0,
+            gas_ohms: None,
+            heater_temp_c: None,
+        }
+    }
+
+    fn settings(max_runtime: Option<Duration>) -> LoopSettings {
+        LoopSettings {
+            read_interval: Duration::from_millis(1),
+            max_consecutive_read_errors: 2,
+            reinit_retry: RetryPolicy {
+                retries: 0,
+                base_delay: Duration::from_millis(0),
+                max_delay: Duration::from_millis(0),
+            },
+            samples_per_point: 1,
+            max_runtime,
+            quiet_hours: None,
+            write_jitter: None,
+        }
+    }
+
+    fn collector(config: &Config) -> (Collector, RecordingSink) {
+        let sink = RecordingSink::default();
+        let collector = Collector::new(config, Box::new(sink.clone())).unwrap();
+        (collector, sink)
+    }
+
+    fn temperatures(records: &[Record]) -> Vec<f64> {
+        records
+            .iter()
+            .flat_map(|record| &record.fields)
+            .filter(|field| field.measurement == "temperature_c")
+            .map(|field| field.value)
+            .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn loop_writes_readings_until_the_sensor_is_gone() {
+        let (mut collector, sink) = collector(&Config::default());
+        let mock = MockSensor::new(vec![Ok(reading(20.0)), Ok(reading(21.0))]);
+        let mut sensors: Vec<Box<dyn EnvSensor>> = vec![Box::new(mock.with_failing_reinit())];
+
+        let result = run(
+            &mut sensors,
+            &mut collector,
+            settings(None),
+            None,
+            std::future::pending(),
+        )
+        .await;
+
+        assert!(matches!(result, Err(SensorError::Init(_))));
+        assert_eq!(
+            temperatures(&sink.records.lock().unwrap()),
+            vec![20.0, 21.0]
+        );
+        let stats = collector.stats().snapshot();
+        assert_eq!(stats.reads_total, 2);
+        assert_eq!(stats.read_errors_total, 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn loop_reinitializes_after_consecutive_failures() {
+        let config = Config {
+            write_events: true,
+            ..Config::default()
+        };
+        let (mut collector, sink) = collector(&config);
+        let mock = MockSensor::new(vec![
+            Err(SensorError::Read("bus error".into())),
+            Ok(reading(20.0)),
+            Err(SensorError::Read("bus error".into())),
+            Err(SensorError::Read("bus error".into())),
+            Ok(reading(22.0)),
+        ]);
+        let mut sensors: Vec<Box<dyn EnvSensor>> = vec![Box::new(mock)];
+
+        let result = run(
+            &mut sensors,
+            &mut collector,
+            settings(Some(Duration::from_millis(50))),
+            None,
+            std::future::pending(),
+        )
+        .await;
+
+        assert!(result.is_ok());
+        let records = sink.records.lock().unwrap();
+        assert_eq!(temperatures(&records), vec![20.0, 22.0]);
+        // A single failure is retried next cycle, only two in a row re-initialize the sensor.
+        let first_reinit = records
+            .iter()
+            .position(|record| record.tags.get("type").map(String::as_str) == Some("reinit"))
+            .unwrap();
+        assert_eq!(temperatures(&records[..first_reinit]), vec![20.0]);
+        assert_eq!(
+            records[first_reinit].measurement.as_deref(),
+            Some(EVENTS_MEASUREMENT)
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn loop_stops_on_shutdown() {
+        let (mut collector, sink) = collector(&Config::default());
+        let mut sensors: Vec<Box<dyn EnvSensor>> =
+            vec![Box::new(MockSensor::new(vec![Ok(reading(20.0))]))];
+        let mut settings = settings(None);
+        settings.read_interval = Duration::from_secs(3600);
+
+        let result = run(
+            &mut sensors,
+            &mut collector,
+            settings,
+            None,
+            sleep(Duration::from_millis(20)),
+        )
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(temperatures(&sink.records.lock().unwrap()), vec![20.0]);
+    }
+
+    #[tokio::test]
+    async fn write_counters_follow_the_sink() {
+        let (mut collector, _) = collector(&Config::default());
+        collector.handle(0, reading(20.0)).await.unwrap();
+        let stats = collector.stats().snapshot();
+        assert_eq!((stats.writes_total, stats.write_errors_total), (1, 0));
+
+        let mut collector = Collector::new(&Config::default(), Box::new(FailingSink)).unwrap();
+        assert!(matches!(
+            collector.handle(0, reading(20.0)).await,
+            Err(HandleError::Write(_))
+        ));
+        let stats = collector.stats().snapshot();
+        assert_eq!((stats.writes_total, stats.write_errors_total), (1, 1));
+    }
+
+    #[test]
+    fn runtime_is_exceeded_once_the_max_runtime_has_passed() {
+        let started = Instant::now();
+        let max_runtime = Some(Duration::from_secs(3600));
+        assert!(!runtime_exceeded(started, started, max_runtime));
+        assert!(!runtime_exceeded(
+            started,
+            started + Duration::from_secs(3599),
+            max_runtime
+        ));
+        assert!(runtime_exceeded(
+            started,
+            started + Duration::from_secs(3600),
+            max_runtime
+        ));
+        assert!(!runtime_exceeded(
+            started,
+            started + Duration::from_secs(86_400 * 365),
+            None
+        ));
+    }
+
+    #[test]
+    fn quality_is_stable_after_the_warmup_and_gas_burn_in() {
+        let warmup = Duration::from_secs(300);
+        let (early, late) = (Duration::from_secs(299), Duration::from_secs(300));
+        assert_eq!(quality(early, warmup, None), Quality::Warmup);
+        assert_eq!(quality(late, warmup, None), Quality::Stable);
+        assert_eq!(quality(late, warmup, Some(false)), Quality::Warmup);
+        assert_eq!(quality(late, warmup, Some(true)), Quality::Stable);
+        assert_eq!(quality(early, warmup, Some(true)), Quality::Warmup);
+        assert_eq!(Quality::Warmup.as_str(), "warmup");
+        assert_eq!(Quality::Stable.as_str(), "stable");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn on_demand_reads_are_answered_but_not_written() {
+        let (mut collector, sink) = collector(&Config::default());
+        let mock = MockSensor::new(vec![Ok(reading(20.0)), Ok(reading(25.0))]);
+        let mut sensors: Vec<Box<dyn EnvSensor>> = vec![Box::new(mock)];
+        let mut settings = settings(None);
+        settings.read_interval = Duration::from_secs(3600);
+        let (requests, receiver) = mpsc::channel(1);
+        let (reply, response) = tokio::sync::oneshot::channel();
+        requests.send(reply).await.unwrap();
+        let answer = Mutex::new(None);
+
+        let result = run(
+            &mut sensors,
+            &mut collector,
+            settings,
+            Some(receiver),
+            async {
+                *answer.lock().unwrap() = Some(response.await.unwrap());
+            },
+        )
+        .await;
+
+        assert!(result.is_ok());
+        let answer = answer.into_inner().unwrap().unwrap().unwrap();
+        assert_eq!(answer.temperature_c, 25.0);
+        // The scheduled reading is the only one written.
+        assert_eq!(temperatures(&sink.records.lock().unwrap()), vec![20.0]);
+    }
+
+    fn uptimes(records: &[Record]) -> Vec<f64> {
+        records
+            .iter()
+            .flat_map(|record| &record.fields)
+            .filter(|field| field.measurement == "sensor_uptime_secs")
+            .map(|field| field.value)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn uptime_resets_on_reinit() {
+        let (mut collector, sink) = collector(&Config::default());
+        collector.channels[0].initialized = Instant::now() - Duration::from_secs(120);
+        collector.handle(0, reading(20.0)).await.unwrap();
+        collector.reset_after_reinit(0);
+        collector.handle(0, reading(20.0)).await.unwrap();
+        assert_eq!(uptimes(&sink.records.lock().unwrap()), vec![120.0, 0.0]);
+    }
+
+    #[test]
+    fn overrun_cycles_skip_the_wait() {
+        let interval = Duration::from_secs(60);
+        assert_eq!(
+            remaining_interval(interval, Duration::from_secs(15)),
+            Duration::from_secs(45)
+        );
+        assert_eq!(
+            remaining_interval(interval, interval),
+            Duration::from_secs(0)
+        );
+        assert_eq!(
+            remaining_interval(interval, Duration::from_secs(75)),
+            Duration::from_secs(0)
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn heartbeats_are_written_on_cycles_without_new_data() {
+        let config = Config {
+            write_heartbeat: true,
+            ..Config::default()
+        };
+        let (mut collector, sink) = collector(&config);
+        let mock = MockSensor::new(vec![
+            Ok(reading(20.0)),
+            Err(SensorError::NoNewData),
+            Ok(reading(21.0)),
+        ]);
+        let mut sensors: Vec<Box<dyn EnvSensor>> = vec![Box::new(mock.with_failing_reinit())];
+
+        let result = run(
+            &mut sensors,
+            &mut collector,
+            settings(None),
+            None,
+            std::future::pending(),
+        )
+        .await;
+
+        assert!(matches!(result, Err(SensorError::Init(_))));
+        let records = sink.records.lock().unwrap();
+        assert_eq!(temperatures(&records), vec![20.0, 21.0]);
+        let heartbeats: Vec<f64> = records
+            .iter()
+            .filter(|record| record.measurement.as_deref() == Some(HEARTBEAT_MEASUREMENT))
+            .map(|record| record.fields[0].value)
+            .collect();
+        // The last cycle gives up on the sensor before its heartbeat.
+        assert_eq!(heartbeats, vec![1.0, 0.0, 1.0, 0.0]);
+    }
+
+    #[tokio::test]
+    async fn clamped_humidity_only_feeds_derived_metrics() {
+        let mut config = Config {
+            clamp_humidity: true,
+            ..Config::default()
+        };
+        // Exactly 0% is implausible by default, long before it could be clamped.
+        config.valid_ranges.humidity_min_percent = 0.0;
+        let (mut collector, sink) = collector(&config);
+        for &humidity_percent in &[100.0, 0.0, 45.0] {
+            let reading = Reading {
+                humidity_percent,
+                ..reading(20.0)
+            };
+            collector.handle(0, reading).await.unwrap();
+        }
+
+        let records = sink.records.lock().unwrap().clone();
+        let values = |measurement: &str| -> Vec<f64> {
+            records
+                .iter()
+                .flat_map(|record| &record.fields)
+                .filter(|field| field.measurement == measurement)
+                .map(|field| field.value)
+                .collect()
+        };
+        assert_eq!(values("relative_humidity"), vec![100.0, 0.0, 45.0]);
+        let dew_points = values("dew_point_c");
+        assert_eq!(dew_points.len(), 3);
+        assert!(dew_points.iter().all(|dew_point| dew_point.is_finite()));
+        assert!(dew_points[0] < 20.0);
+        assert!((dew_points[1] - dew_point_celsius(20.0, 0.5)).abs() < 1e-9);
+        assert!((dew_points[2] - dew_point_celsius(20.0, 45.0)).abs() < 1e-9);
+
+        let (mut collector, _) = self::collector(&Config {
+            clamp_humidity: true,
+            ..Config::default()
+        });
+        let zero = Reading {
+            humidity_percent: 0.0,
+            ..reading(20.0)
+        };
+        assert!(matches!(
+            collector.handle(0, zero).await,
+            Err(HandleError::Implausible(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn invalid_gas_is_kept_out_of_the_baseline() {
+        let with_gas = |gas_ohms| Reading {
+            gas_ohms: Some(gas_ohms),
+            ..reading(20.0)
+        };
+        for &omit_invalid_gas in &[false, true] {
+            let config = Config {
+                min_valid_gas_ohms: Some(1000.0),
+                omit_invalid_gas,
+                ..Config::default()
+            };
+            let (mut collector, sink) = collector(&config);
+            let gas = collector.names.gas.clone();
+
+            collector.handle(0, with_gas(0.0)).await.unwrap();
+            assert_eq!(collector.channels[0].rejected_gas, 1);
+            assert_eq!(collector.channels[0].gas_baseline.ohms(), None);
+            collector.handle(0, with_gas(50_000.0)).await.unwrap();
+            assert_eq!(collector.channels[0].rejected_gas, 1);
+            assert!(collector.channels[0].gas_baseline.ohms().is_some());
+
+            let written: Vec<f64> = sink
+                .records
+                .lock()
+                .unwrap()
+                .iter()
+                .flat_map(|record| &record.fields)
+                .filter(|field| field.measurement == gas)
+                .map(|field| field.value)
+                .collect();
+            if omit_invalid_gas {
+                assert_eq!(written, vec![50_000.0]);
+            } else {
+                assert_eq!(written, vec![0.0, 50_000.0]);
+            }
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Skipping writes of values that haven't changed.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Remembers the last value written per measurement so unchanged ones can be left out.
+#[derive(Debug, Clone)]
+pub struct Deduplicator {
+    max_interval: Duration,
+    /// Largest difference from the last written value that still counts as unchanged.
+    epsilon: f64,
+    /// Last written value and its timestamp in nanoseconds since the epoch.
+    last: HashMap<String, (f64, i64)>,
+}
+
+impl Deduplicator {
+    pub fn new(max_interval: Duration, epsilon: f64) -> Self {
+        Deduplicator {
+            max_interval,
+            epsilon,
+            last: HashMap::new(),
+        }
+    }
+
+    /// Whether `value` at `timestamp` should be written: when it differs from the last value
+    /// written for `measurement` by more than `epsilon`, or that one is at least
+    /// `max_interval` old so the series doesn't look dead. Written values become the new
+    /// reference, so slow drift is still written once it adds up.
+    pub fn should_write(&mut self, measurement: &str, value: f64, timestamp: i64) -> bool {
+        let max_interval = self.max_interval.as_nanos() as i64;
+        let write = match self.last.get(measurement) {
+            Some(&(last_value, last_timestamp)) => {
+                (value - last_value).abs() > self.epsilon
+                    || timestamp.saturating_sub(last_timestamp) >= max_interval
+            }
+            None => true,
+        };
+        if write {
+            self.last
+                .insert(measurement.to_string(), (value, timestamp));
+        }
+        write
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: i64 = 1_000_000_000;
+
+    #[test]
+    fn unchanged_values_are_skipped_until_the_max_interval() {
+        let mut dedup = Deduplicator::new(Duration::from_secs(300), 0.01);
+        assert!(dedup.should_write("pressure_hpa", 1013.2, 0));
+        assert!(!dedup.should_write("pressure_hpa", 1013.2, 60 * SECOND));
+        assert!(!dedup.should_write("pressure_hpa", 1013.205, 120 * SECOND));
+        assert!(dedup.should_write("pressure_hpa", 1013.2, 300 * SECOND));
+        assert!(!dedup.should_write("pressure_hpa", 1013.2, 360 * SECOND));
+    }
+
+    #[test]
+    fn fields_are_deduplicated_independently() {
+        let mut dedup = Deduplicator::new(Duration::from_secs(300), 0.01);
+        assert!(dedup.should_write("pressure_hpa", 1013.2, 0));
+        assert!(dedup.should_write("temperature_c", 21.0, 0));
+        assert!(!dedup.should_write("pressure_hpa", 1013.2, 60 * SECOND));
+        assert!(dedup.should_write("temperature_c", 21.5, 60 * SECOND));
+    }
+
+    #[test]
+    fn slow_drift_is_written_once_it_adds_up() {
+        let mut dedup = Deduplicator::new(Duration::from_secs(3600), 0.1);
+        assert!(dedup.should_write("temperature_c", 20.0, 0));
+        assert!(!dedup.should_write("temperature_c", 20.06, SECOND));
+        assert!(!dedup.should_write("temperature_c", 20.09, 2 * SECOND));
+        assert!(dedup.should_write("temperature_c", 20.12, 3 * SECOND));
+        assert!(!dedup.should_write("temperature_c", 20.2, 4 * SECOND));
+    }
+}
@@ -0,0 +1,442 @@
+//! Measurements derived from the raw temperature, humidity and pressure readings.
+
+use crate::reading::{Field, Reading};
+use crate::units::{celsius_to_fahrenheit, TemperatureUnit};
+
+/// Magnus-Tetens coefficients over water (Sonntag 1990), valid roughly -45°C..60°C.
+const MAGNUS_A: f64 = 17.62;
+const MAGNUS_B: f64 = 243.12;
+
+/// Magnus coefficients over ice (Sonntag 1990), used below 0°C.
+const MAGNUS_ICE_A: f64 = 22.46;
+const MAGNUS_ICE_B: f64 = 272.62;
+
+/// Saturation vapor pressure at 0°C in hPa.
+const MAGNUS_E0_HPA: f64 = 6.112;
+
+/// Water vapor gas constant folded with the hPa -> Pa and kg -> g conversions:
+/// 100 / 461.5 J/(kg·K) * 1000 = 216.7.
+const VAPOR_DENSITY_FACTOR: f64 = 216.7;
+
+const KELVIN_OFFSET: f64 = 273.15;
+
+/// International barometric formula constants for the standard atmosphere.
+const BAROMETRIC_SCALE_M: f64 = 44330.0;
+const BAROMETRIC_EXPONENT: f64 = 1.0 / 5.255;
+
+/// Standard atmosphere temperature lapse rate in K/m.
+const LAPSE_RATE_K_M: f64 = 0.0065;
+const SEA_LEVEL_EXPONENT: f64 = -5.257;
+
+/// Rothfusz regression coefficients for the NOAA heat index, in °F and percent RH.
+const HEAT_INDEX_COEFFICIENTS: [f64; 9] = [
+    -42.379,
+    2.049_015_23,
+    10.143_331_27,
+    -0.224_755_41,
+    -0.006_837_83,
+    -0.054_817_17,
+    0.001_228_74,
+    0.000_852_82,
+    -0.000_001_99,
+];
+
+/// Below these the heat index regression isn't valid and the air temperature is used instead.
+const HEAT_INDEX_MIN_TEMP_C: f64 = 27.0;
+const HEAT_INDEX_MIN_RH_PERCENT: f64 = 40.0;
+
+/// Lowest relative humidity fed into the logarithm, keeps dry air finite.
+const MIN_RH_PERCENT: f64 = 0.01;
+
+fn clamp_rh(rh_percent: f64) -> f64 {
+    if rh_percent.is_nan() {
+        return MIN_RH_PERCENT;
+    }
+    rh_percent.clamp(MIN_RH_PERCENT, 100.0)
+}
+
+/// Relative humidity limited to `min_percent..=max_percent`, keeping artifacts like a reading
+/// of exactly 100% from saturating the derived metrics.
+pub fn clamp_humidity(rh_percent: f64, min_percent: f64, max_percent: f64) -> f64 {
+    rh_percent.max(min_percent).min(max_percent)
+}
+
+fn clamp_temp(temp_c: f64) -> f64 {
+    temp_c.clamp(-45.0, 60.0)
+}
+
+/// Dew point in °C using the Magnus-Tetens approximation.
+pub fn dew_point_celsius(temp_c: f64, rh_percent: f64) -> f64 {
+    let t = clamp_temp(temp_c);
+    let gamma = (clamp_rh(rh_percent) / 100.0).ln() + (MAGNUS_A * t) / (MAGNUS_B + t);
+    (MAGNUS_B * gamma) / (MAGNUS_A - gamma)
+}
+
+/// Saturation vapor pressure in hPa, over ice below 0°C and over water otherwise.
+pub fn saturation_vapor_pressure_hpa(temp_c: f64) -> f64 {
+    let t = clamp_temp(temp_c);
+    let (a, b) = if t < 0.0 {
+        (MAGNUS_ICE_A, MAGNUS_ICE_B)
+    } else {
+        (MAGNUS_A, MAGNUS_B)
+    };
+    MAGNUS_E0_HPA * ((a * t) / (b + t)).exp()
+}
+
+/// Actual vapor pressure of the air in hPa, the saturation pressure scaled by relative humidity.
+pub fn vapor_pressure_hpa(temp_c: f64, rh_percent: f64) -> f64 {
+    saturation_vapor_pressure_hpa(temp_c) * clamp_rh(rh_percent) / 100.0
+}
+
+/// Absolute humidity in g/m³ from the actual vapor pressure and the ideal gas law.
+pub fn absolute_humidity_g_m3(temp_c: f64, rh_percent: f64) -> f64 {
+    let t = clamp_temp(temp_c);
+    VAPOR_DENSITY_FACTOR * vapor_pressure_hpa(t, rh_percent) / (KELVIN_OFFSET + t)
+}
+
+/// Approximate altitude in meters from station pressure using the international barometric
+/// formula. Returns NaN for non-positive pressures instead of an arbitrary altitude.
+pub fn altitude_meters(pressure_hpa: f64, sea_level_hpa: f64) -> f64 {
+    if !(pressure_hpa > 0.0 && sea_level_hpa > 0.0) {
+        return f64::NAN;
+    }
+    BAROMETRIC_SCALE_M * (1.0 - (pressure_hpa / sea_level_hpa).powf(BAROMETRIC_EXPONENT))
+}
+
+/// Reduce station pressure to sea level (QNH), correcting for the air column temperature
+/// with the standard lapse rate. At 0 m this returns the station pressure unchanged.
+pub fn sea_level_pressure_hpa(station_hpa: f64, altitude_m: f64, temp_c: f64) -> f64 {
+    let lapse = LAPSE_RATE_K_M * altitude_m;
+    station_hpa * (1.0 - lapse / (temp_c + lapse + KELVIN_OFFSET)).powf(SEA_LEVEL_EXPONENT)
+}
+
+/// Leaf vapor pressure deficit in kPa: saturation pressure at the leaf, approximated as air
+/// temperature plus `leaf_offset_c`, minus the actual vapor pressure of the air. The offset is
+/// negative for leaves colder than the air. Never negative.
+pub fn vapor_pressure_deficit_kpa(temp_c: f64, rh_percent: f64, leaf_offset_c: f64) -> f64 {
+    let leaf_saturation = saturation_vapor_pressure_hpa(temp_c + leaf_offset_c);
+    let air_vapor = vapor_pressure_hpa(temp_c, rh_percent);
+    ((leaf_saturation - air_vapor) / 10.0).max(0.0)
+}
+
+/// Whether moisture is likely to condense on a surface, approximated as air temperature plus
+/// `surface_offset_c`, i.e. the surface is at or below the dew point. Like the leaf offset of
+/// [`vapor_pressure_deficit_kpa`], the offset is negative for surfaces colder than the air.
+pub fn condensation_risk(temp_c: f64, rh_percent: f64, surface_offset_c: f64) -> bool {
+    temp_c + surface_offset_c <= dew_point_celsius(temp_c, rh_percent)
+}
+
+/// Apparent ("feels like") temperature in °C from the NOAA heat index regression. The
+/// regression only holds from about 27°C and 40% RH, below either of those the air temperature
+/// is returned unchanged.
+pub fn heat_index_celsius(temp_c: f64, rh_percent: f64) -> f64 {
+    if temp_c < HEAT_INDEX_MIN_TEMP_C || rh_percent < HEAT_INDEX_MIN_RH_PERCENT {
+        return temp_c;
+    }
+    let t = celsius_to_fahrenheit(temp_c);
+    let rh = rh_percent.min(100.0);
+    let c = HEAT_INDEX_COEFFICIENTS;
+    let heat_index_f = c[0]
+        + c[1] * t
+        + c[2] * rh
+        + c[3] * t * rh
+        + c[4] * t * t
+        + c[5] * rh * rh
+        + c[6] * t * t * rh
+        + c[7] * t * rh * rh
+        + c[8] * t * t * rh * rh;
+    (heat_index_f - 32.0) * 5.0 / 9.0
+}
+
+/// One calculation deriving fields from every reading, kept per sensor so it can carry state
+/// across readings.
+pub trait DerivedMetric: Send {
+    fn compute(&mut self, reading: &Reading) -> Vec<Field>;
+}
+
+/// `dew_point_<unit>`.
+#[derive(Debug, Clone, Copy)]
+pub struct DewPoint {
+    unit: TemperatureUnit,
+}
+
+impl DewPoint {
+    pub fn new(unit: TemperatureUnit) -> Self {
+        DewPoint { unit }
+    }
+}
+
+impl DerivedMetric for DewPoint {
+    fn compute(&mut self, reading: &Reading) -> Vec<Field> {
+        let dew_point_c = dew_point_celsius(reading.temperature_c, reading.humidity_percent);
+        vec![Field::new(
+            &format!("dew_point_{}", self.unit.suffix()),
+            self.unit.convert(dew_point_c),
+        )]
+    }
+}
+
+/// `absolute_humidity_g_m3`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AbsoluteHumidity;
+
+impl DerivedMetric for AbsoluteHumidity {
+    fn compute(&mut self, reading: &Reading) -> Vec<Field> {
+        vec![Field::new(
+            "absolute_humidity_g_m3",
+            absolute_humidity_g_m3(reading.temperature_c, reading.humidity_percent),
+        )]
+    }
+}
+
+/// Which derived metrics to compute.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DerivedFlags {
+    pub dew_point: bool,
+    pub absolute_humidity: bool,
+}
+
+/// The enabled derived metrics for one sensor, in the order their fields are written.
+pub fn derived_metrics(
+    flags: DerivedFlags,
+    temp_unit: TemperatureUnit,
+) -> Vec<Box<dyn DerivedMetric>> {
+    let mut metrics: Vec<Box<dyn DerivedMetric>> = Vec::new();
+    if flags.dew_point {
+        metrics.push(Box::new(DewPoint::new(temp_unit)));
+    }
+    if flags.absolute_humidity {
+        metrics.push(Box::new(AbsoluteHumidity));
+    }
+    metrics
+}
+
+/// Run every metric in `metrics` on `reading`, collecting their fields in order.
+pub fn compute_all(metrics: &mut [Box<dyn DerivedMetric>], reading: &Reading) -> Vec<Field> {
+    metrics
+        .iter_mut()
+        .flat_map(|metric| metric.compute(reading))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} isn't within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn dew_point_matches_reference_table() {
+        for &(temp_c, rh_percent, dew_point_c) in &[
+            (20.0, 50.0, 9.3),
+            (30.0, 80.0, 26.2),
+            (25.0, 100.0, 25.0),
+            (10.0, 60.0, 2.6),
+            (0.0, 50.0, -9.2),
+            (-10.0, 80.0, -12.8),
+        ] {
+            assert_close(dew_point_celsius(temp_c, rh_percent), dew_point_c, 0.1);
+        }
+    }
+
+    #[test]
+    fn dew_point_stays_finite_for_impossible_inputs() {
+        for &(temp_c, rh_percent) in &[
+            (20.0, 0.0),
+            (20.0, -5.0),
+            (20.0, f64::NAN),
+            (20.0, 130.0),
+            (-80.0, 50.0),
+            (90.0, 50.0),
+        ] {
+            assert!(dew_point_celsius(temp_c, rh_percent).is_finite());
+        }
+        assert_close(dew_point_celsius(20.0, 130.0), 20.0, 1e-9);
+    }
+
+    #[test]
+    fn absolute_humidity_matches_reference_table() {
+        assert_close(absolute_humidity_g_m3(20.0, 50.0), 8.65, 0.1);
+        assert_close(absolute_humidity_g_m3(30.0, 80.0), 24.27, 0.1);
+    }
+
+    #[test]
+    fn saturation_below_freezing_is_over_ice() {
+        // Over ice 2.60 hPa at -10°C, against 2.86 hPa over supercooled water.
+        assert_close(saturation_vapor_pressure_hpa(-10.0), 2.60, 0.01);
+        assert_close(absolute_humidity_g_m3(-10.0, 50.0), 1.07, 0.02);
+    }
+
+    #[test]
+    fn absolute_humidity_of_dry_air_is_near_zero() {
+        let dry = absolute_humidity_g_m3(20.0, 0.0);
+        assert!((0.0..0.01).contains(&dry));
+    }
+
+    #[test]
+    fn altitude_matches_standard_atmosphere() {
+        assert_close(altitude_meters(1013.25, 1013.25), 0.0, 1e-9);
+        assert_close(altitude_meters(898.76, 1013.25), 1000.0, 1.0);
+        assert_close(altitude_meters(795.01, 1013.25), 2000.0, 1.0);
+        assert!(altitude_meters(1030.0, 1013.25) < 0.0);
+    }
+
+    #[test]
+    fn altitude_of_non_positive_pressure_is_nan() {
+        assert!(altitude_meters(0.0, 1013.25).is_nan());
+        assert!(altitude_meters(-5.0, 1013.25).is_nan());
+        assert!(altitude_meters(1000.0, 0.0).is_nan());
+    }
+
+    #[test]
+    fn sea_level_pressure_at_sea_level_is_unchanged() {
+        assert_close(sea_level_pressure_hpa(1002.3, 0.0, 18.0), 1002.3, 1e-9);
+    }
+
+    #[test]
+    fn sea_level_pressure_at_500_m() {
+        // The standard atmosphere has 954.6 hPa at 500 m, 15°C at sea level.
+        assert_close(sea_level_pressure_hpa(954.6, 500.0, 15.0), 1013.25, 1.0);
+        // Colder air is denser, so the same station pressure reduces to more at sea level.
+        assert!(sea_level_pressure_hpa(954.6, 500.0, -10.0) > 1016.0);
+        assert!(sea_level_pressure_hpa(954.6, 500.0, 30.0) < 1011.0);
+    }
+
+    #[test]
+    fn vpd_with_a_cooler_leaf() {
+        // Leaf at 23°C saturates at 28.1 hPa, the air holds 60% of 31.7 hPa.
+        assert_close(vapor_pressure_deficit_kpa(25.0, 60.0, -2.0), 0.91, 0.02);
+        assert!(vapor_pressure_deficit_kpa(25.0, 60.0, 0.0) > 1.2);
+    }
+
+    #[test]
+    fn vpd_is_never_negative_at_saturation() {
+        assert_eq!(vapor_pressure_deficit_kpa(25.0, 100.0, -2.0), 0.0);
+        assert_eq!(vapor_pressure_deficit_kpa(25.0, 120.0, -5.0), 0.0);
+        assert_close(vapor_pressure_deficit_kpa(25.0, 100.0, 0.0), 0.0, 1e-9);
+    }
+
+    #[test]
+    fn heat_index_matches_noaa_chart() {
+        // 95 °F at 50% feels like 105 °F, 86 °F at 60% like 91 °F.
+        assert_close(heat_index_celsius(35.0, 50.0), 40.6, 0.2);
+        assert_close(heat_index_celsius(30.0, 60.0), 32.8, 0.2);
+        assert!(heat_index_celsius(32.0, 70.0) > 40.0);
+    }
+
+    #[test]
+    fn heat_index_outside_the_regression_is_the_air_temperature() {
+        assert_eq!(heat_index_celsius(20.0, 80.0), 20.0);
+        assert_eq!(heat_index_celsius(35.0, 30.0), 35.0);
+        assert_eq!(heat_index_celsius(-5.0, 90.0), -5.0);
+    }
+
+    #[test]
+    fn cold_surface_trips_the_condensation_risk() {
+        // The dew point at 20 °C and 60% is about 12 °C.
+        assert!(condensation_risk(20.0, 60.0, -10.0));
+        assert!(!condensation_risk(20.0, 60.0, -5.0));
+        assert!(!condensation_risk(20.0, 60.0, 0.0));
+        // Saturated air condenses on a surface at air temperature.
+        assert!(condensation_risk(20.0, 100.0, 0.0));
+    }
+
+    #[test]
+    fn vapor_pressures_match_psychrometric_tables() {
+        // Saturation over water: 6.11 hPa at 0°C, 23.39 at 20°C, 42.46 at 30°C. The Magnus
+        // formula stays within 0.5% of the tables.
+        assert_close(saturation_vapor_pressure_hpa(0.0), 6.11, 0.01);
+        assert_close(saturation_vapor_pressure_hpa(20.0), 23.39, 0.1);
+        assert_close(saturation_vapor_pressure_hpa(30.0), 42.46, 0.2);
+        assert_close(vapor_pressure_hpa(25.0, 50.0), 15.84, 0.08);
+        assert_close(
+            vapor_pressure_hpa(20.0, 100.0),
+            saturation_vapor_pressure_hpa(20.0),
+            1e-9,
+        );
+    }
+
+    fn reading() -> Reading {
+        Reading {
+            timestamp: 1_700_000_000_000_000_000,
+            temperature_c: 20.0,
+            humidity_percent: 50.0,
+            pressure_hpa: 1013.25,
+            gas_ohms: None,
+            heater_temp_c: None,
+        }
+    }
+
+    fn measurements(fields: &[Field]) -> Vec<&str> {
+        fields
+            .iter()
+            .map(|field| field.measurement.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn only_enabled_metrics_are_computed_in_order() {
+        let all = DerivedFlags {
+            dew_point: true,
+            absolute_humidity: true,
+        };
+        let mut metrics = derived_metrics(all, TemperatureUnit::Fahrenheit);
+        let fields = compute_all(&mut metrics, &reading());
+        assert_eq!(
+            measurements(&fields),
+            vec!["dew_point_f", "absolute_humidity_g_m3"]
+        );
+        assert_close(
+            fields[0].value,
+            celsius_to_fahrenheit(dew_point_celsius(20.0, 50.0)),
+            1e-9,
+        );
+
+        let humidity_only = DerivedFlags {
+            dew_point: false,
+            absolute_humidity: true,
+        };
+        let mut metrics = derived_metrics(humidity_only, TemperatureUnit::Celsius);
+        let fields = compute_all(&mut metrics, &reading());
+        assert_eq!(measurements(&fields), vec!["absolute_humidity_g_m3"]);
+
+        let none = DerivedFlags {
+            dew_point: false,
+            absolute_humidity: false,
+        };
+        assert!(derived_metrics(none, TemperatureUnit::Celsius).is_empty());
+    }
+
+    #[test]
+    fn metrics_keep_their_state_across_readings() {
+        struct Count(f64);
+        impl DerivedMetric for Count {
+            fn compute(&mut self, _: &Reading) -> Vec<Field> {
+                self.0 += 1.0;
+                vec![Field::new("count", self.0)]
+            }
+        }
+        let mut metrics: Vec<Box<dyn DerivedMetric>> = vec![Box::new(Count(0.0))];
+        compute_all(&mut metrics, &reading());
+        let fields = compute_all(&mut metrics, &reading());
+        assert_eq!(fields, vec![Field::new("count", 2.0)]);
+    }
+
+    #[test]
+    fn humidity_is_clamped_into_the_bounds() {
+        assert_eq!(clamp_humidity(100.0, 0.5, 99.5), 99.5);
+        assert_eq!(clamp_humidity(0.0, 0.5, 99.5), 0.5);
+        assert_eq!(clamp_humidity(45.0, 0.5, 99.5), 45.0);
+        assert_eq!(clamp_humidity(120.0, 10.0, 90.0), 90.0);
+        // Clamped away from saturation, the dew point stays below the air temperature.
+        assert!(dew_point_celsius(20.0, clamp_humidity(100.0, 0.5, 99.5)) < 20.0);
+    }
+}
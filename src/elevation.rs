@@ -0,0 +1,251 @@
+//! Looking up the station altitude from its coordinates.
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Open-Meteo's elevation API, which needs no key.
+pub const DEFAULT_ELEVATION_URL: &str = "https://api.open-meteo.com/v1/elevation";
+
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Something that knows the elevation of a point on the map.
+#[async_trait]
+pub trait ElevationSource: Send + Sync {
+    /// Elevation in meters above sea level.
+    async fn elevation(&self, latitude: f64, longitude: f64) -> Result<f64, String>;
+}
+
+/// Queries an Open-Meteo compatible API: `GET <url>?latitude=..&longitude=..`, answered with
+/// `{"elevation": [meters]}`.
+pub struct OpenMeteo {
+    http: reqwest::Client,
+    url: String,
+}
+
+impl OpenMeteo {
+    pub fn new(url: &str) -> Result<Self, String> {
+        let http = reqwest::Client::builder()
+            .timeout(LOOKUP_TIMEOUT)
+            .build()
+            .map_err(|e| format!("failed to build HTTP client: {}", e))?;
+        Ok(OpenMeteo {
+            http,
+            url: url.to_string(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct ElevationResponse {
+    elevation: Vec<f64>,
+}
+
+#[async_trait]
+impl ElevationSource for OpenMeteo {
+    async fn elevation(&self, latitude: f64, longitude: f64) -> Result<f64, String> {
+        let response = self
+            .http
+            .get(&self.url)
+            .query(&[("latitude", latitude), ("longitude", longitude)])
+            .send()
+            .await
+            .map_err(|e| format!("{} is unreachable: {}", self.url, e))?;
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            return Err(format!("{} returned {} {}", self.url, status, text.trim()));
+        }
+        let parsed: ElevationResponse = serde_json::from_str(&text)
+            .map_err(|e| format!("{} returned unexpected JSON: {}", self.url, e))?;
+        parsed
+            .elevation
+            .first()
+            .copied()
+            .filter(|meters| meters.is_finite())
+            .ok_or_else(|| format!("{} returned no elevation", self.url))
+    }
+}
+
+/// A looked up altitude, remembered with the coordinates it belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CachedAltitude {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude_m: f64,
+}
+
+impl CachedAltitude {
+    /// The cached altitude, if `path` holds one for these coordinates.
+    pub fn load(path: &Path, latitude: f64, longitude: f64) -> Option<f64> {
+        let contents = fs::read_to_string(path).ok()?;
+        let cached: CachedAltitude = serde_json::from_str(&contents).ok()?;
+        if cached.latitude == latitude && cached.longitude == longitude {
+            Some(cached.altitude_m)
+        } else {
+            None
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let contents = serde_json::to_string(self)
+            .map_err(|e| format!("failed to serialize altitude: {}", e))?;
+        fs::write(path, contents).map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+}
+
+/// Station altitude for sea-level pressure: from `cache` when it has one for the coordinates,
+/// else looked up from `source` and cached. Any failure along the way falls back to
+/// `configured`, as does leaving the coordinates unset.
+pub async fn station_altitude(
+    source: &dyn ElevationSource,
+    coordinates: Option<(f64, f64)>,
+    cache: Option<&Path>,
+    configured: f64,
+) -> f64 {
+    let (latitude, longitude) = match coordinates {
+        Some(coordinates) => coordinates,
+        None => return configured,
+    };
+    if let Some(altitude_m) = cache.and_then(|path| CachedAltitude::load(path, latitude, longitude))
+    {
+        info!("Using cached station altitude of {} m.", altitude_m);
+        return altitude_m;
+    }
+    match source.elevation(latitude, longitude).await {
+        Ok(altitude_m) => {
+            info!(
+                "Looked up station altitude of {} m for {}, {}.",
+                altitude_m, latitude, longitude
+            );
+            if let Some(path) = cache {
+                let cached = CachedAltitude {
+                    latitude,
+                    longitude,
+                    altitude_m,
+                };
+                if let Err(e) = cached.save(path) {
+                    warn!("Failed to cache station altitude: {}", e);
+                }
+            }
+            altitude_m
+        }
+        Err(e) => {
+            warn!(
+                "Station altitude lookup failed, using STATION_ALTITUDE_M of {} m: {}",
+                configured, e
+            );
+            configured
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Answers every lookup with `result`, counting the lookups.
+    struct StubSource {
+        result: Result<f64, String>,
+        lookups: AtomicU32,
+    }
+
+    impl StubSource {
+        fn new(result: Result<f64, String>) -> Self {
+            StubSource {
+                result,
+                lookups: AtomicU32::new(0),
+            }
+        }
+
+        fn lookups(&self) -> u32 {
+            self.lookups.load(Ordering::SeqCst)
+        }
+    }
+
+    #[async_trait]
+    impl ElevationSource for StubSource {
+        async fn elevation(&self, _: f64, _: f64) -> Result<f64, String> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            self.result.clone()
+        }
+    }
+
+    fn cache_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "atmosphere-altitude-{}-{}.json",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn looked_up_altitudes_are_cached() {
+        let path = cache_path("lookup");
+        let source = StubSource::new(Ok(34.0));
+        let coordinates = Some((52.52, 13.405));
+
+        let altitude = station_altitude(&source, coordinates, Some(&path), 0.0).await;
+        assert_eq!(altitude, 34.0);
+        assert_eq!(CachedAltitude::load(&path, 52.52, 13.405), Some(34.0));
+
+        let altitude = station_altitude(&source, coordinates, Some(&path), 0.0).await;
+        assert_eq!(altitude, 34.0);
+        assert_eq!(source.lookups(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn cached_altitudes_only_apply_to_their_coordinates() {
+        let path = cache_path("moved");
+        let cached = CachedAltitude {
+            latitude: 52.52,
+            longitude: 13.405,
+            altitude_m: 34.0,
+        };
+        cached.save(&path).unwrap();
+        let source = StubSource::new(Ok(520.0));
+
+        let altitude = station_altitude(&source, Some((48.14, 11.58)), Some(&path), 0.0).await;
+        assert_eq!(altitude, 520.0);
+        assert_eq!(source.lookups(), 1);
+        assert_eq!(CachedAltitude::load(&path, 48.14, 11.58), Some(520.0));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_lookups_fall_back_to_the_configured_altitude() {
+        let path = cache_path("failed");
+        let source = StubSource::new(Err("unreachable".into()));
+
+        let altitude = station_altitude(&source, Some((52.52, 13.405)), Some(&path), 120.0).await;
+        assert_eq!(altitude, 120.0);
+        assert!(!path.exists());
+
+        let altitude = station_altitude(&source, None, Some(&path), 120.0).await;
+        assert_eq!(altitude, 120.0);
+        assert_eq!(source.lookups(), 1);
+    }
+
+    #[tokio::test]
+    async fn lookups_work_without_a_cache() {
+        let source = StubSource::new(Ok(34.0));
+        assert_eq!(
+            station_altitude(&source, Some((52.52, 13.405)), None, 0.0).await,
+            34.0
+        );
+        let unwritable = Path::new("/nonexistent/atmosphere/altitude.json");
+        assert_eq!(
+            station_altitude(&source, Some((52.52, 13.405)), Some(unwritable), 0.0).await,
+            34.0
+        );
+        assert_eq!(source.lookups(), 2);
+    }
+}
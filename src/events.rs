@@ -0,0 +1,112 @@
+//! Operational events written as points next to the readings, e.g. for Grafana annotations.
+
+use crate::reading::Record;
+use std::collections::BTreeMap;
+
+/// Measurement events are written to, with a `value` of 1 and a `message` string field.
+pub const EVENTS_MEASUREMENT: &str = "atmosphere_events";
+
+/// Measurement of the point written every cycle, with a `has_new_data` field.
+pub const HEARTBEAT_MEASUREMENT: &str = "heartbeat";
+
+/// What happened, written as the `type` tag.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    /// A sensor was re-initialized after read failures.
+    Reinit,
+    /// The stabilization wait is over and readings start.
+    Stabilized,
+    /// A threshold alert fired.
+    Alert,
+}
+
+impl EventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::Reinit => "reinit",
+            EventKind::Stabilized => "stabilized",
+            EventKind::Alert => "alert",
+        }
+    }
+}
+
+/// The record for an event of `kind` at `timestamp`, with `tags` and the kind as `type`.
+pub fn event_record(
+    kind: EventKind,
+    message: &str,
+    timestamp: i64,
+    tags: &BTreeMap<String, String>,
+) -> Record {
+    let mut tags = tags.clone();
+    tags.insert("type".to_string(), kind.as_str().to_string());
+    let mut record = Record::new(timestamp, tags);
+    record.measurement = Some(EVENTS_MEASUREMENT.to_string());
+    record.push("value", 1.0);
+    record
+        .text
+        .insert("message".to_string(), message.to_string());
+    record
+}
+
+/// The heartbeat of a cycle at `timestamp` tagged `tags`, `has_new_data` being whether any sensor
+/// returned a fresh reading.
+pub fn heartbeat_record(
+    has_new_data: bool,
+    timestamp: i64,
+    tags: &BTreeMap<String, String>,
+) -> Record {
+    let mut record = Record::new(timestamp, tags.clone());
+    record.measurement = Some(HEARTBEAT_MEASUREMENT.to_string());
+    record.push("has_new_data", if has_new_data { 1.0 } else { 0.0 });
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reading::Field;
+
+    fn host_tags() -> BTreeMap<String, String> {
+        let mut tags = BTreeMap::new();
+        tags.insert("host".to_string(), "pi".to_string());
+        tags
+    }
+
+    #[test]
+    fn events_are_tagged_with_their_type_and_carry_a_message() {
+        let record = event_record(
+            EventKind::Reinit,
+            "sensor 0 re-initialized",
+            42,
+            &host_tags(),
+        );
+        assert_eq!(record.timestamp, 42);
+        assert_eq!(record.tags.get("host").map(String::as_str), Some("pi"));
+        assert_eq!(record.tags.get("type").map(String::as_str), Some("reinit"));
+        assert_eq!(record.measurement.as_deref(), Some(EVENTS_MEASUREMENT));
+        assert_eq!(record.fields, vec![Field::new("value", 1.0)]);
+        assert_eq!(
+            record.text.get("message").map(String::as_str),
+            Some("sensor 0 re-initialized")
+        );
+
+        let record = event_record(
+            EventKind::Alert,
+            "temperature_c of 31 crossed",
+            43,
+            &host_tags(),
+        );
+        assert_eq!(record.tags.get("type").map(String::as_str), Some("alert"));
+    }
+
+    #[test]
+    fn heartbeat_is_a_point_of_its_own() {
+        let record = heartbeat_record(false, 42, &host_tags());
+        assert_eq!(record.measurement.as_deref(), Some(HEARTBEAT_MEASUREMENT));
+        assert_eq!(record.fields, vec![Field::new("has_new_data", 0.0)]);
+        assert_eq!(
+            heartbeat_record(true, 42, &host_tags()).fields[0].value,
+            1.0
+        );
+    }
+}
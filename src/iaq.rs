@@ -0,0 +1,440 @@
+//! Indoor air quality estimation from gas resistance and relative humidity.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Relative humidity considered ideal for indoor air.
+const HUMIDITY_REFERENCE_PERCENT: f64 = 40.0;
+
+/// Share of the quality score contributed by humidity and gas, out of 100.
+const HUMIDITY_WEIGHT: f64 = 25.0;
+const GAS_WEIGHT: f64 = 75.0;
+
+/// Relative change of gas resistance per %RH, resistance falls as humidity rises.
+const HUMIDITY_GAS_COEFFICIENT: f64 = 0.03;
+
+/// Highest value on the IAQ scale, reported for the worst air.
+pub const IAQ_MAX: f64 = 500.0;
+
+/// eCO2 reported for clean air, about the outdoor CO2 level.
+const ECO2_CLEAN_PPM: f64 = 400.0;
+/// eCO2 reported once the gas resistance has fallen to nothing.
+const ECO2_MAX_PPM: f64 = 5000.0;
+
+/// IAQ on a 0-500 scale in the style of Bosch's index: 0 is excellent air, 500 is hazardous.
+///
+/// A quality score out of 100 is built from how far humidity strays from ~40% (25%) and how far
+/// the [humidity-compensated](humidity_compensated_gas) gas resistance has fallen below
+/// `baseline_ohms`, the clean-air baseline of compensated resistances (75%), then inverted onto
+/// 0-500.
+pub fn iaq_index(gas_ohms: f64, rh_percent: f64, baseline_ohms: f64) -> f64 {
+    let humidity_offset = humidity_offset(rh_percent);
+    let humidity_score = if humidity_offset > 0.0 {
+        (100.0 - HUMIDITY_REFERENCE_PERCENT - humidity_offset)
+            / (100.0 - HUMIDITY_REFERENCE_PERCENT)
+            * HUMIDITY_WEIGHT
+    } else {
+        (HUMIDITY_REFERENCE_PERCENT + humidity_offset) / HUMIDITY_REFERENCE_PERCENT
+            * HUMIDITY_WEIGHT
+    };
+
+    let compensated_ohms = humidity_compensated_gas(gas_ohms, rh_percent);
+    let gas_score = if baseline_ohms > 0.0 && compensated_ohms < baseline_ohms {
+        (compensated_ohms.max(0.0) / baseline_ohms) * GAS_WEIGHT
+    } else {
+        GAS_WEIGHT
+    };
+
+    let quality = (humidity_score + gas_score).clamp(0.0, 100.0);
+    (100.0 - quality) / 100.0 * IAQ_MAX
+}
+
+/// Gas resistance as it would read at the reference humidity of the IAQ score, so readings
+/// taken at different humidities can be compared. Resistances read in humid air are raised,
+/// those read in dry air lowered.
+pub fn humidity_compensated_gas(gas_ohms: f64, rh_percent: f64) -> f64 {
+    gas_ohms * (HUMIDITY_GAS_COEFFICIENT * humidity_offset(rh_percent)).exp()
+}
+
+/// Estimated CO2-equivalent in ppm, from how far the
+/// [humidity-compensated](humidity_compensated_gas) gas resistance has fallen below
+/// `baseline_ohms`, the clean-air baseline of compensated resistances: 400 ppm at or above the
+/// baseline, rising linearly to 5000 ppm.
+///
+/// This is the usual VOC-to-eCO2 heuristic: it assumes CO2 rises along with the VOCs the sensor
+/// reacts to, e.g. with people in the room. It is an estimate, not a CO2 measurement like an
+/// NDIR sensor makes.
+pub fn eco2_ppm(gas_ohms: f64, baseline_ohms: f64, rh_percent: f64) -> f64 {
+    let compensated_ohms = humidity_compensated_gas(gas_ohms, rh_percent);
+    let ratio = if baseline_ohms > 0.0 {
+        (compensated_ohms.max(0.0) / baseline_ohms).min(1.0)
+    } else {
+        1.0
+    };
+    ECO2_CLEAN_PPM + (1.0 - ratio) * (ECO2_MAX_PPM - ECO2_CLEAN_PPM)
+}
+
+/// How far `rh_percent`, clamped to 0-100, is above the reference humidity.
+fn humidity_offset(rh_percent: f64) -> f64 {
+    rh_percent.clamp(0.0, 100.0) - HUMIDITY_REFERENCE_PERCENT
+}
+
+/// Whether `gas_ohms` is a plausible resistance rather than the zero or near-zero reads a
+/// wrong heater setting produces. Everything is valid without a `min_valid_ohms`.
+pub fn gas_is_valid(gas_ohms: f64, min_valid_ohms: Option<f64>) -> bool {
+    min_valid_ohms.is_none_or(|min_valid_ohms| gas_ohms >= min_valid_ohms)
+}
+
+/// Clean-air reference for gas resistance.
+///
+/// Tracks the maximum gas resistance seen within a rolling `window`, so the baseline follows
+/// slow sensor drift and forgets old peaks. Gas readings are unreliable while the heater plate
+/// burns in after power-on, so the baseline only reports as burned in after `burn_in`.
+#[derive(Debug, Clone)]
+pub struct GasBaseline {
+    window: Duration,
+    burn_in: Duration,
+    /// When the first reading was fed, samples are timed relative to it.
+    started: Option<Instant>,
+    /// Running time before `started`, carried over from a restored state.
+    carried_over: Duration,
+    /// Candidate maxima as (seconds since `started`, ohms) in arrival order with strictly
+    /// decreasing values. Restored samples are from before `started` and thus negative.
+    samples: VecDeque<(f64, f64)>,
+}
+
+impl GasBaseline {
+    pub fn new(window: Duration, burn_in: Duration) -> Self {
+        GasBaseline {
+            window,
+            burn_in,
+            started: None,
+            carried_over: Duration::from_secs(0),
+            samples: VecDeque::new(),
+        }
+    }
+
+    fn seconds_since_start(&self, now: Instant) -> f64 {
+        self.started
+            .map(|started| now.saturating_duration_since(started).as_secs_f64())
+            .unwrap_or(0.0)
+    }
+
+    /// Feed a gas resistance reading taken at `now` and return the updated baseline.
+    pub fn update(&mut self, now: Instant, gas_ohms: f64) -> f64 {
+        self.started.get_or_insert(now);
+        let now_secs = self.seconds_since_start(now);
+        let window_secs = self.window.as_secs_f64();
+        while let Some(&(taken, _)) = self.samples.front() {
+            if now_secs - taken > window_secs {
+                self.samples.pop_front();
+            } else {
+                break;
+            }
+        }
+        while let Some(&(_, ohms)) = self.samples.back() {
+            if ohms <= gas_ohms {
+                self.samples.pop_back();
+            } else {
+                break;
+            }
+        }
+        self.samples.push_back((now_secs, gas_ohms));
+        self.ohms().unwrap_or(gas_ohms)
+    }
+
+    /// Highest gas resistance within the window, if any reading has been seen.
+    pub fn ohms(&self) -> Option<f64> {
+        self.samples.front().map(|&(_, ohms)| ohms)
+    }
+
+    /// Whether the sensor has been read for long enough to trust the baseline.
+    pub fn is_burned_in(&self, now: Instant) -> bool {
+        self.started
+            .map(|started| {
+                now.saturating_duration_since(started) + self.carried_over >= self.burn_in
+            })
+            .unwrap_or(false)
+    }
+
+    /// The baseline as of `now`, with sample times as ages so it can outlive the process.
+    pub fn state(&self, now: Instant) -> BaselineState {
+        let now_secs = self.seconds_since_start(now);
+        BaselineState {
+            running_secs: now_secs + self.carried_over.as_secs_f64(),
+            samples: self
+                .samples
+                .iter()
+                .map(|&(taken, ohms)| (now_secs - taken, ohms))
+                .collect(),
+        }
+    }
+
+    /// Pick up from a saved `state`, taken `elapsed` ago, before the first reading is fed.
+    /// Samples that have aged out of the window meanwhile are dropped.
+    pub fn restore(&mut self, now: Instant, state: &BaselineState, elapsed: Duration) {
+        let elapsed_secs = elapsed.as_secs_f64();
+        let window_secs = self.window.as_secs_f64();
+        self.started = Some(now);
+        // Only the burn-in cares how long the sensor ran, which also keeps garbage from a
+        // corrupt file in range.
+        self.carried_over =
+            Duration::from_secs_f64(state.running_secs.max(0.0).min(self.burn_in.as_secs_f64()));
+        self.samples = state
+            .samples
+            .iter()
+            .map(|&(age, ohms)| (-(age + elapsed_secs), ohms))
+            .filter(|&(taken, _)| -taken <= window_secs)
+            .collect();
+    }
+}
+
+/// A [`GasBaseline`] detached from the process clock.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BaselineState {
+    /// How long the sensor had been read for, counting towards the burn-in.
+    pub running_secs: f64,
+    /// Candidate maxima as (age in seconds, ohms), oldest first.
+    pub samples: Vec<(f64, f64)>,
+}
+
+/// The gas baselines of all sensors as saved to `BASELINE_STATE_PATH`, keyed by sensor label.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BaselineFile {
+    /// Seconds since the Unix epoch.
+    pub saved_at: u64,
+    pub channels: BTreeMap<String, BaselineState>,
+}
+
+impl BaselineFile {
+    /// Baselines saved now.
+    pub fn new(channels: BTreeMap<String, BaselineState>) -> Self {
+        BaselineFile {
+            saved_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since_epoch| since_epoch.as_secs())
+                .unwrap_or(0),
+            channels,
+        }
+    }
+
+    /// Read the saved baselines. `Ok(None)` when `path` doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Option<BaselineFile>, String> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("failed to read {}: {}", path.display(), e)),
+        };
+        serde_json::from_str(&contents)
+            .map(Some)
+            .map_err(|e| format!("failed to parse {}: {}", path.display(), e))
+    }
+
+    /// Write the baselines to a temporary file next to `path` and move it into place, so a
+    /// crash mid-write leaves the previous state intact.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let contents = serde_json::to_string(self)
+            .map_err(|e| format!("failed to serialize gas baselines: {}", e))?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, contents)
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+
+    /// How long ago the state was saved, zero if that is in the future.
+    pub fn age(&self, now: SystemTime) -> Duration {
+        let saved_at = UNIX_EPOCH + Duration::from_secs(self.saved_at);
+        now.duration_since(saved_at)
+            .unwrap_or_else(|_| Duration::from_secs(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compensation_raises_resistance_in_humid_air() {
+        let gas = 50_000.0;
+        assert_eq!(
+            humidity_compensated_gas(gas, HUMIDITY_REFERENCE_PERCENT),
+            gas
+        );
+        assert!(humidity_compensated_gas(gas, 60.0) > gas);
+        assert!(humidity_compensated_gas(gas, 20.0) < gas);
+        assert!(humidity_compensated_gas(gas, 80.0) > humidity_compensated_gas(gas, 60.0));
+    }
+
+    #[test]
+    fn iaq_compares_compensated_gas_with_the_baseline() {
+        let baseline = 50_000.0;
+        // The same raw resistance reads as cleaner air when it was taken in humid air.
+        let dry = iaq_index(40_000.0, 35.0, baseline);
+        let humid = iaq_index(40_000.0, 45.0, baseline);
+        assert!(humid < dry);
+        // Reading the baseline itself at the reference humidity is excellent air.
+        assert!(iaq_index(baseline, HUMIDITY_REFERENCE_PERCENT, baseline) < 1e-9);
+    }
+
+    #[test]
+    fn eco2_is_clean_air_at_the_baseline() {
+        let baseline = humidity_compensated_gas(50_000.0, 20.0);
+        assert!(
+            (eco2_ppm(baseline, baseline, HUMIDITY_REFERENCE_PERCENT) - ECO2_CLEAN_PPM).abs()
+                < 1e-9
+        );
+        assert!(
+            (eco2_ppm(2.0 * baseline, baseline, HUMIDITY_REFERENCE_PERCENT) - ECO2_CLEAN_PPM).abs()
+                < 1e-9
+        );
+    }
+
+    #[test]
+    fn eco2_rises_as_gas_falls() {
+        let baseline = 50_000.0;
+        let readings: Vec<f64> = [50_000.0, 40_000.0, 25_000.0, 10_000.0, 0.0]
+            .iter()
+            .map(|&gas| eco2_ppm(gas, baseline, HUMIDITY_REFERENCE_PERCENT))
+            .collect();
+        assert!(readings.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!((readings[2] - 2700.0).abs() < 1e-9);
+        assert!((readings[4] - ECO2_MAX_PPM).abs() < 1e-9);
+    }
+
+    #[test]
+    fn eco2_compensates_for_humidity() {
+        let baseline = 50_000.0;
+        let humid_ohms = 40_000.0;
+        assert!(
+            (eco2_ppm(humid_ohms, baseline, 60.0)
+                - eco2_ppm(
+                    humidity_compensated_gas(humid_ohms, 60.0),
+                    baseline,
+                    HUMIDITY_REFERENCE_PERCENT
+                ))
+            .abs()
+                < 1e-9
+        );
+        assert!(eco2_ppm(humid_ohms, baseline, 60.0) < eco2_ppm(humid_ohms, baseline, 40.0));
+    }
+
+    #[test]
+    fn eco2_without_baseline_is_clean_air() {
+        assert_eq!(eco2_ppm(10_000.0, 0.0, 60.0), ECO2_CLEAN_PPM);
+    }
+
+    #[test]
+    fn iaq_worsens_as_gas_resistance_drops() {
+        let mut baseline = GasBaseline::new(Duration::from_secs(3600), Duration::from_secs(0));
+        let start = Instant::now();
+        let series = [60_000.0, 60_000.0, 52_000.0, 40_000.0, 28_000.0, 15_000.0];
+        let indices: Vec<f64> = series
+            .iter()
+            .enumerate()
+            .map(|(i, &gas)| {
+                let now = start + Duration::from_secs(60 * i as u64);
+                let baseline_ohms = baseline.update(now, gas);
+                iaq_index(gas, HUMIDITY_REFERENCE_PERCENT, baseline_ohms)
+            })
+            .collect();
+        assert!(indices[0] < 1e-9);
+        assert!(indices.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(indices[5] > indices[1] + 100.0);
+        assert!(indices.iter().all(|index| (0.0..=IAQ_MAX).contains(index)));
+    }
+
+    #[test]
+    fn iaq_penalizes_humidity_away_from_the_reference() {
+        let at_reference = iaq_index(50_000.0, HUMIDITY_REFERENCE_PERCENT, 50_000.0);
+        assert!(iaq_index(50_000.0, 10.0, 50_000.0) > at_reference);
+        assert!(iaq_index(50_000.0, 90.0, 50_000.0) > at_reference);
+        assert!(iaq_index(0.0, 100.0, 50_000.0) <= IAQ_MAX);
+    }
+
+    #[test]
+    fn baseline_is_the_maximum_within_the_window() {
+        let mut baseline = GasBaseline::new(Duration::from_secs(100), Duration::from_secs(0));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        assert_eq!(baseline.ohms(), None);
+        assert_eq!(baseline.update(at(0), 40_000.0), 40_000.0);
+        assert_eq!(baseline.update(at(10), 50_000.0), 50_000.0);
+        assert_eq!(baseline.update(at(20), 30_000.0), 50_000.0);
+        assert_eq!(baseline.update(at(60), 45_000.0), 50_000.0);
+        // The peak at 10 s ages out, the next highest within the window takes over.
+        assert_eq!(baseline.update(at(111), 20_000.0), 45_000.0);
+        assert_eq!(baseline.update(at(161), 25_000.0), 25_000.0);
+        assert_eq!(baseline.ohms(), Some(25_000.0));
+    }
+
+    #[test]
+    fn baseline_burns_in_after_the_burn_in_time() {
+        let mut baseline = GasBaseline::new(Duration::from_secs(100), Duration::from_secs(30));
+        let start = Instant::now();
+        assert!(!baseline.is_burned_in(start));
+        baseline.update(start, 40_000.0);
+        assert!(!baseline.is_burned_in(start + Duration::from_secs(29)));
+        assert!(baseline.is_burned_in(start + Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn baseline_survives_a_restart() {
+        let mut baseline = GasBaseline::new(Duration::from_secs(100), Duration::from_secs(60));
+        let start = Instant::now();
+        baseline.update(start, 50_000.0);
+        baseline.update(start + Duration::from_secs(40), 30_000.0);
+        let state = baseline.state(start + Duration::from_secs(50));
+        assert_eq!(state.running_secs, 50.0);
+        assert_eq!(state.samples, vec![(50.0, 50_000.0), (10.0, 30_000.0)]);
+
+        let mut restored = GasBaseline::new(Duration::from_secs(100), Duration::from_secs(60));
+        let restart = Instant::now();
+        restored.restore(restart, &state, Duration::from_secs(20));
+        assert_eq!(restored.ohms(), Some(50_000.0));
+        assert!(!restored.is_burned_in(restart + Duration::from_secs(9)));
+        assert!(restored.is_burned_in(restart + Duration::from_secs(10)));
+        // The peak was 70 s old at the restart and ages out 30 s later.
+        assert_eq!(
+            restored.update(restart + Duration::from_secs(31), 20_000.0),
+            30_000.0
+        );
+    }
+
+    #[test]
+    fn baseline_file_round_trips() {
+        let path =
+            std::env::temp_dir().join(format!("atmosphere-baseline-{}.json", std::process::id()));
+        let mut channels = BTreeMap::new();
+        channels.insert(
+            "indoor".to_string(),
+            BaselineState {
+                running_secs: 3600.0,
+                samples: vec![(120.0, 52_000.0), (5.0, 48_500.5)],
+            },
+        );
+        let file = BaselineFile::new(channels);
+        file.save(&path).unwrap();
+        let loaded = BaselineFile::load(&path);
+        fs::write(&path, "{\"saved_at\": ").unwrap();
+        let corrupt = BaselineFile::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, Ok(Some(file)));
+        assert!(corrupt.is_err());
+        assert_eq!(BaselineFile::load(&path), Ok(None));
+    }
+
+    #[test]
+    fn gas_below_the_minimum_is_invalid() {
+        assert!(gas_is_valid(0.0, None));
+        assert!(gas_is_valid(50_000.0, Some(1000.0)));
+        assert!(gas_is_valid(1000.0, Some(1000.0)));
+        assert!(!gas_is_valid(999.0, Some(1000.0)));
+        assert!(!gas_is_valid(0.0, Some(1000.0)));
+        assert!(!gas_is_valid(-5.0, Some(1000.0)));
+    }
+}
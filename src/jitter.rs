@@ -0,0 +1,85 @@
+//! Random delays spreading the writes of nodes that started at the same time.
+
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Draws delays from 0 up to and including `max`, at millisecond resolution.
+///
+/// Uses SplitMix64, which is plenty for spreading load and keeps a seeded sequence the same
+/// across platforms.
+#[derive(Debug, Clone)]
+pub struct Jitter {
+    max: Duration,
+    state: u64,
+}
+
+impl Jitter {
+    pub fn new(max: Duration, seed: u64) -> Self {
+        Jitter { max, state: seed }
+    }
+
+    /// Seeded from the clock and the process id, so nodes booted together still differ.
+    pub fn from_entropy(max: Duration) -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_nanos() as u64)
+            .unwrap_or(0);
+        Jitter::new(max, nanos ^ (process::id() as u64).rotate_left(32))
+    }
+
+    /// The next delay.
+    pub fn next_delay(&mut self) -> Duration {
+        jitter_delay(self.max, self.next_u64())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// The delay `random` picks from 0 to `max`, in whole milliseconds.
+pub fn jitter_delay(max: Duration, random: u64) -> Duration {
+    let max_ms = max.as_millis() as u64;
+    Duration::from_millis(random % max_ms.saturating_add(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_stay_within_zero_to_max() {
+        let max = Duration::from_millis(250);
+        assert_eq!(jitter_delay(max, 0), Duration::from_millis(0));
+        assert_eq!(jitter_delay(max, 250), max);
+        assert_eq!(jitter_delay(max, 251), Duration::from_millis(0));
+        assert_eq!(
+            jitter_delay(max, u64::MAX),
+            Duration::from_millis(u64::MAX % 251)
+        );
+        assert_eq!(
+            jitter_delay(Duration::from_millis(0), 12345),
+            Duration::from_millis(0)
+        );
+
+        let mut jitter = Jitter::new(max, 7);
+        assert!((0..1000).all(|_| jitter.next_delay() <= max));
+    }
+
+    #[test]
+    fn seeded_delays_are_repeatable_and_spread() {
+        let max = Duration::from_secs(10);
+        let delays = |seed| {
+            let mut jitter = Jitter::new(max, seed);
+            (0..20).map(|_| jitter.next_delay()).collect::<Vec<_>>()
+        };
+        assert_eq!(delays(42), delays(42));
+        assert_ne!(delays(42), delays(43));
+        let first = delays(42);
+        assert!(first.iter().any(|&delay| delay != first[0]));
+    }
+}
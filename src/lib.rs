@@ -0,0 +1,35 @@
+//! Reading a BME680 environmental sensor and writing its measurements out.
+//!
+//! [`sensor::Sensor`] wraps the device and produces [`reading::Reading`]s, and
+//! [`collector::run`] reads any [`sensor::EnvSensor`] in a loop, deriving further metrics and
+//! delivering them to sinks.
+
+pub mod aggregate;
+pub mod alert;
+pub mod backfill;
+pub mod buffer;
+pub mod calibration;
+pub mod collector;
+pub mod config;
+pub mod dedup;
+pub mod derived;
+pub mod elevation;
+pub mod events;
+pub mod http;
+pub mod iaq;
+pub mod jitter;
+pub mod location;
+pub mod logging;
+pub mod mqtt;
+pub mod otel;
+pub mod reading;
+pub mod retry;
+pub mod schedule;
+pub mod sensor;
+pub mod settings;
+pub mod sink;
+pub mod smoothing;
+pub mod stats;
+pub mod trend;
+pub mod units;
+pub mod zambretti;
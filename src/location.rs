@@ -0,0 +1,72 @@
+//! The station's coordinates, written as a point of their own for maps.
+
+use crate::reading::Record;
+use std::collections::BTreeMap;
+
+/// Measurement the coordinates are written to, as `latitude` and `longitude` fields.
+pub const LOCATION_MEASUREMENT: &str = "location";
+
+/// Check that `latitude` and `longitude` are degrees within -90 to 90 and -180 to 180.
+pub fn validate_coordinates(latitude: f64, longitude: f64) -> Result<(), String> {
+    if !(-90.0..=90.0).contains(&latitude) {
+        return Err(format!(
+            "latitude {} is outside of -90 to 90 degrees",
+            latitude
+        ));
+    }
+    if !(-180.0..=180.0).contains(&longitude) {
+        return Err(format!(
+            "longitude {} is outside of -180 to 180 degrees",
+            longitude
+        ));
+    }
+    Ok(())
+}
+
+/// The record placing a station tagged `tags` at `latitude` and `longitude` at `timestamp`.
+pub fn location_record(
+    latitude: f64,
+    longitude: f64,
+    timestamp: i64,
+    tags: &BTreeMap<String, String>,
+) -> Record {
+    let mut record = Record::new(timestamp, tags.clone());
+    record.measurement = Some(LOCATION_MEASUREMENT.to_string());
+    record.push("latitude", latitude);
+    record.push("longitude", longitude);
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reading::Field;
+
+    #[test]
+    fn coordinates_must_be_on_the_globe() {
+        assert!(validate_coordinates(52.52, 13.405).is_ok());
+        assert!(validate_coordinates(-90.0, 180.0).is_ok());
+        assert!(validate_coordinates(90.0, -180.0).is_ok());
+        let err = validate_coordinates(90.5, 0.0).unwrap_err();
+        assert!(err.contains("latitude"), "{}", err);
+        let err = validate_coordinates(0.0, -181.0).unwrap_err();
+        assert!(err.contains("longitude"), "{}", err);
+        assert!(validate_coordinates(f64::NAN, 0.0).is_err());
+    }
+
+    #[test]
+    fn coordinates_are_fields_of_the_location_measurement() {
+        let mut tags = BTreeMap::new();
+        tags.insert("host".to_string(), "pi".to_string());
+        let record = location_record(52.52, 13.405, 42, &tags);
+        assert_eq!(record.measurement.as_deref(), Some(LOCATION_MEASUREMENT));
+        assert_eq!(record.tags, tags);
+        assert_eq!(
+            record.fields,
+            vec![
+                Field::new("latitude", 52.52),
+                Field::new("longitude", 13.405)
+            ]
+        );
+    }
+}
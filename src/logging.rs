@@ -0,0 +1,74 @@
+//! Log output setup.
+
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+/// Directive used when `RUST_LOG` is unset or invalid.
+const DEFAULT_FILTER: &str = "info";
+
+/// Install the global subscriber, filtered by `RUST_LOG` and formatted as `text`, `json` or
+/// sent to the systemd journal with `journald`.
+///
+/// The journal gets the level as `PRIORITY` and event and span fields as fields of their own,
+/// so `journalctl` can filter on them. Without a journal to connect to, e.g. outside systemd,
+/// `journald` falls back to `text`.
+pub fn init(format: &str) -> Result<(), String> {
+    let filter =
+        || EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter());
+    match format.trim().to_ascii_lowercase().as_str() {
+        "text" => builder.try_init(),
+        "json" => builder.json().try_init(),
+        "journald" => match tracing_journald::layer() {
+            Ok(journald) => tracing_subscriber::registry()
+                .with(filter())
+                .with(journald)
+                .try_init()
+                .map_err(|e| e.into()),
+            Err(e) => {
+                eprintln!(
+                    "Can't connect to the systemd journal, logging as text instead: {}",
+                    e
+                );
+                builder.try_init()
+            }
+        },
+        _ => {
+            return Err(format!(
+                "invalid log format {:?}, expected text, json or journald",
+                format
+            ))
+        }
+    }
+    .map_err(|e| format!("failed to install the subscriber: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_initialize_without_panicking() {
+        assert!(init("yaml").unwrap_err().contains("invalid log format"));
+        assert_eq!(init("json"), Ok(()));
+        // There is only one global subscriber, installing a second is an error, not a panic.
+        assert!(init(" TEXT ").unwrap_err().contains("failed to install"));
+        // Journal or not, journald gets as far as installing the subscriber.
+        assert!(init("journald").unwrap_err().contains("failed to install"));
+    }
+
+    #[test]
+    fn journald_layer_logs_or_is_unavailable() {
+        match tracing_journald::layer() {
+            Ok(journald) => {
+                let subscriber = tracing_subscriber::registry().with(journald);
+                tracing::subscriber::with_default(subscriber, || {
+                    tracing::info!(sensor = 0, "journald layer test");
+                });
+            }
+            // No journal socket outside systemd, which init falls back to text for.
+            Err(e) => assert!(!e.to_string().is_empty()),
+        }
+    }
+}
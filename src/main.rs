@@ -1,106 +1,478 @@
-use bme680::{
-    Bme680, FieldDataCondition, I2CAddress, IIRFilterSize, OversamplingSetting, PowerMode,
-    SettingsBuilder,
+use atmosphere::backfill::{backfill, TAG_COLUMNS};
+use atmosphere::collector::{self, Collector, LoopSettings};
+use atmosphere::config::{
+    parse_run_mode, parse_stabilize_mode, Args, Config, RunMode, StabilizeMode,
 };
-use dotenv::var;
-use futures::stream;
-use influxdb2_client::models::DataPoint;
-use linux_embedded_hal::*;
+use atmosphere::elevation::{station_altitude, OpenMeteo};
+use atmosphere::events::EventKind;
+use atmosphere::http::{
+    self, parse_listen_addr, HttpState, ListenAddr, SharedReading, SocketCleanup,
+};
+use atmosphere::location::validate_coordinates;
+use atmosphere::logging;
+use atmosphere::mqtt::MqttPublisher;
+use atmosphere::otel;
+use atmosphere::retry::retry;
+use atmosphere::schedule::parse_daily_window;
+use atmosphere::sensor::{
+    blocking_io, read_interval, stabilize_duration, EnvSensor, GasStability, HeaterProfile, Sensor,
+};
+use atmosphere::settings::{
+    parse_heater_profile, parse_i2c_address, parse_iir_filter, parse_interface, parse_oversampling,
+    parse_power_mode, parse_sensors,
+};
+use atmosphere::sink;
+use bme680::{PowerMode, SettingsBuilder};
+use clap::Parser;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::{mpsc, RwLock};
 use tokio::time::sleep;
-use tracing::{error, info};
+use tracing::{error, info, warn};
+
+/// On-demand reads waiting for the read loop before `POST /read` turns requests away.
+const READ_REQUEST_QUEUE: usize = 4;
+
+/// Backfills write at least this many readings per request.
+const BACKFILL_BATCH_SIZE: usize = 100;
 
 #[tokio::main]
 async fn main() -> Result<(), ()> {
-    tracing_subscriber::fmt::init();
-    dotenv::dotenv().map_err(|e| error!("Failed to load .env file for configuration: {:?}", e))?;
-    let influx_token =
-        var("INFLUX_TOKEN").map_err(|e| error!("Failed to load INFLUX_TOKEN: {:?}", e))?;
-    let influx_bucket =
-        var("INFLUX_BUCKET").map_err(|e| error!("Failed to load INFLUX_BUCKET: {:?}", e))?;
-    let influx_address =
-        var("INFLUX_ADDRESS").map_err(|e| error!("Failed to load INFLUX_BUCKET: {:?}", e))?;
-    let influx_organization = var("INFLUX_ORGANIZATION")
-        .map_err(|e| error!("Failed to load INFLUX_ORGANIZATION: {:?}", e))?;
-
-    let host_tag = var("HOSTNAME").unwrap_or_else(|_| "unknown".into());
-
-    let temperature_offset: f32 = var("TEMP_OFFSET")
-        .unwrap_or_else(|_| "0".into())
-        .parse()
-        .map_err(|e| error!("Failed to load temp offset from TEMP_OFFSET: {:?}", e))?;
-
-    let i2c =
-        I2cdev::new("/dev/i2c-1").map_err(|e| error!("Failed to load I2C device: {:?}", e))?;
-    let mut delayer = Delay {};
-    let mut dev = Bme680::init(i2c, &mut delayer, I2CAddress::Secondary)
-        .map_err(|e| error!("Failed to init BME680. {:?}", e))?;
+    let args = Args::parse();
+    // Loaded before logging is set up so `.env` can hold RUST_LOG and LOG_FORMAT.
+    let dotenv = dotenv::dotenv();
+    let log_format = std::env::var("LOG_FORMAT").unwrap_or_else(|_| "text".into());
+    if let Err(e) = logging::init(&log_format) {
+        eprintln!("Failed to load LOG_FORMAT: {}", e);
+        return Err(());
+    }
+    if let Err(e) = dotenv {
+        info!("No .env file loaded: {:?}", e);
+    }
+    let mut config = Config::load(&args).map_err(|e| error!("{}", e))?;
+    let run_mode =
+        parse_run_mode(&config.run_mode).map_err(|e| error!("Failed to load RUN_MODE: {}", e))?;
+    let stabilize_mode = parse_stabilize_mode(&config.stabilize_mode)
+        .map_err(|e| error!("Failed to load STABILIZE_MODE: {}", e))?;
+    if config.dry_run {
+        warn!(
+            "DRY RUN: readings are logged and not written to {}.",
+            config.output
+        );
+    }
+
+    if let Some(path) = &args.backfill {
+        return run_backfill(path, &config).await;
+    }
+
+    let coordinates = config.latitude.zip(config.longitude);
+    if coordinates.is_none() && (config.latitude.is_some() || config.longitude.is_some()) {
+        warn!(
+            "Only one of LATITUDE and LONGITUDE is set, not looking up the station altitude or \
+             writing its location."
+        );
+    }
+    if let Some((latitude, longitude)) = coordinates {
+        validate_coordinates(latitude, longitude)
+            .map_err(|e| error!("Failed to load LATITUDE and LONGITUDE: {}", e))?;
+    }
+    if coordinates.is_some() {
+        match OpenMeteo::new(&config.elevation_url) {
+            Ok(source) => {
+                let cache = config.altitude_cache_path.as_ref().map(Path::new);
+                config.station_altitude_m =
+                    station_altitude(&source, coordinates, cache, config.station_altitude_m).await;
+            }
+            Err(e) => warn!("Can't look up the station altitude: {}", e),
+        }
+    }
+
+    let metrics_addr = match &config.metrics_addr {
+        Some(addr) => Some(
+            parse_listen_addr(addr)
+                .map_err(|e| error!("Failed to parse METRICS_ADDR {}: {}", addr, e))?,
+        ),
+        None => None,
+    };
+
+    let sensor_config = &config.sensor;
+    parse_interface(&sensor_config.interface)
+        .map_err(|e| error!("Failed to load INTERFACE: {}", e))?;
+    let humidity_oversampling = parse_oversampling(&sensor_config.humidity_oversampling)
+        .map_err(|e| error!("Failed to load HUMIDITY_OVERSAMPLING: {}", e))?;
+    let pressure_oversampling = parse_oversampling(&sensor_config.pressure_oversampling)
+        .map_err(|e| error!("Failed to load PRESSURE_OVERSAMPLING: {}", e))?;
+    let temperature_oversampling = parse_oversampling(&sensor_config.temperature_oversampling)
+        .map_err(|e| error!("Failed to load TEMPERATURE_OVERSAMPLING: {}", e))?;
+    let iir_filter = parse_iir_filter(&sensor_config.iir_filter)
+        .map_err(|e| error!("Failed to load IIR_FILTER: {}", e))?;
+    match parse_power_mode(&sensor_config.power_mode)
+        .map_err(|e| error!("Failed to load POWER_MODE: {}", e))?
+    {
+        PowerMode::ForcedMode => info!(
+            "Using forced mode: the sensor sleeps between readings, trading sample rate for lower \
+             power draw and less self-heating."
+        ),
+        PowerMode::SleepMode => {
+            error!("Failed to load POWER_MODE: sleep mode never takes a reading.");
+            return Err(());
+        }
+    }
+
+    let i2c_device = sensor_config.i2c_device.as_str();
+    let specs = match &sensor_config.sensors {
+        Some(sensors) => {
+            parse_sensors(sensors).map_err(|e| error!("Failed to load SENSORS: {}", e))?
+        }
+        None => Vec::new(),
+    };
+    let addresses = if specs.is_empty() {
+        vec![parse_i2c_address(&sensor_config.i2c_address)
+            .map_err(|e| error!("Failed to load I2C_ADDRESS: {}", e))?]
+    } else {
+        specs.iter().map(|spec| spec.address).collect()
+    };
 
     let settings = SettingsBuilder::new()
-        .with_humidity_oversampling(OversamplingSetting::OS2x)
-        .with_pressure_oversampling(OversamplingSetting::OS4x)
-        .with_temperature_oversampling(OversamplingSetting::OS8x)
-        .with_temperature_filter(IIRFilterSize::Size3)
-        .with_gas_measurement(Duration::from_millis(1500), 320, 25)
-        .with_run_gas(true)
-        .with_temperature_offset(temperature_offset)
+        .with_humidity_oversampling(humidity_oversampling)
+        .with_pressure_oversampling(pressure_oversampling)
+        .with_temperature_oversampling(temperature_oversampling)
+        .with_temperature_filter(iir_filter)
+        .with_gas_measurement(
+            Duration::from_millis(sensor_config.gas_heater_duration_ms),
+            sensor_config.gas_heater_temp_c,
+            sensor_config.ambient_temp_c,
+        )
+        .with_run_gas(sensor_config.measure_gas)
+        .with_temperature_offset(sensor_config.temperature_offset)
         .build();
-    dev.set_sensor_settings(&mut delayer, settings)
-        .map_err(|e| error!("Failed to set BME680 sensor settings. {:?}", e))?;
-    let mut profile_dur = dev
-        .get_profile_dur(&settings.0)
-        .map_err(|e| error!("Failed to get profile duration: {:?}", e))?;
-
+    if !sensor_config.measure_gas {
+        info!("Gas measurement disabled, skipping the heater and gas-based metrics.");
+    }
+    let heater_profile = match &sensor_config.gas_heater_profile {
+        Some(profile) => HeaterProfile::new(
+            parse_heater_profile(profile)
+                .map_err(|e| error!("Failed to load GAS_HEATER_PROFILE: {}", e))?,
+        ),
+        None => None,
+    };
+    if let Some(profile) = &heater_profile {
+        if sensor_config.measure_gas {
+            info!("Cycling the gas heater through {:?} C.", profile.temps());
+            if !profile.temps().contains(&sensor_config.gas_heater_temp_c) {
+                warn!(
+                    "GAS_HEATER_PROFILE doesn't include GAS_HEATER_TEMP_C of {} C, IAQ won't be \
+                     computed.",
+                    sensor_config.gas_heater_temp_c
+                );
+            }
+        }
+    }
+    let mut profile_dur = Duration::from_secs(0);
+    let mut sensors: Vec<Box<dyn EnvSensor>> = Vec::new();
+    let init_retry = config.init_retry.policy();
+    for address in addresses {
+        let mut sensor = retry(&init_retry, "Sensor init", || {
+            let result = blocking_io(|| Sensor::new(i2c_device, address, settings));
+            async move { result }
+        })
+        .await
+        .map_err(|e| error!("{}, giving up after {} retries.", e, init_retry.retries))?;
+        if let Some(profile) = &heater_profile {
+            sensor = sensor.with_heater_profile(profile.clone());
+        }
+        if sensor_config.track_ambient_temp {
+            sensor = sensor.with_ambient_tracking(sensor_config.ambient_temp_threshold_c);
+        }
+        if sensor_config.require_gas_valid && sensor_config.measure_gas {
+            sensor = sensor.with_gas_validation().map_err(|e| error!("{}", e))?;
+        }
+        profile_dur = profile_dur.max(sensor.profile_duration());
+        sensors.push(Box::new(sensor));
+    }
     info!("Profile duration set to: {:?}", &profile_dur);
-    profile_dur *=3;
-    info!("Tripling duration to: {:?}", profile_dur);
 
-    let client = influxdb2_client::Client::new(influx_address, influx_token);
+    let requested_interval = config.read_interval_secs.map(Duration::from_secs);
+    let (read_interval, clamped) = read_interval(profile_dur, requested_interval);
+    match requested_interval {
+        Some(requested) if clamped => warn!(
+            "READ_INTERVAL_SECS of {:?} is shorter than the profile duration, clamping to {:?}.",
+            requested, read_interval
+        ),
+        Some(_) => info!("Reading every {:?}.", read_interval),
+        None => info!("Tripling duration to: {:?}", read_interval),
+    }
 
-    info!("Waiting 5m for device to stabilize before reading.");
-    sleep(Duration::from_secs(5*60)).await;
-    info!("Starting readings.");
+    let mqtt = match &config.mqtt.broker {
+        Some(broker) => {
+            info!("Publishing readings to MQTT broker {}", broker);
+            Some(
+                MqttPublisher::connect(
+                    broker,
+                    &config.mqtt,
+                    &config.host,
+                    sensor_config.measure_gas,
+                )
+                .map_err(|e| error!("Failed to set up MQTT: {}", e))?,
+            )
+        }
+        None => None,
+    };
 
-    loop {
-        dev.set_sensor_mode(&mut delayer, PowerMode::ForcedMode)
-            .map_err(|e| error!("Failed to set PowerMode::ForcedMode {:?}", e))?;
-        let (data, state) = dev
-            .get_sensor_data(&mut delayer)
-            .map_err(|e| error!("Failed to get sensor reading {:?}", e))?;
-
-        if state == FieldDataCondition::NewData {
-            let points = vec![
-                DataPoint::builder("temperature_c")
-                    .tag("host", &host_tag)
-                    .field("value", data.temperature_celsius() as f64)
-                    .build()
-                    .map_err(|e| error!("Failed to create data point temperature_c {:?}", e))?,
-                DataPoint::builder("relative_humidity")
-                    .tag("host", &host_tag)
-                    .field("value", data.humidity_percent() as f64)
-                    .build()
-                    .map_err(|e| error!("Failed to create data point relative_humidity {:?}", e))?,
-                DataPoint::builder("pressure_hpa")
-                    .tag("host", &host_tag)
-                    .field("value", data.pressure_hpa() as f64)
-                    .build()
-                    .map_err(|e| error!("Failed to create data point pressure_hpa {:?}", e))?,
-                DataPoint::builder("gas_resistance_ohms")
-                    .tag("host", &host_tag)
-                    .field("value", data.gas_resistance_ohm() as f64)
-                    .build()
-                    .map_err(|e| {
-                        error!("Failed to create data point gas_resistance_ohms {:?}", e)
-                    })?,
-            ];
-
-            match client
-                .write(&influx_organization, &influx_bucket, stream::iter(points))
-                .await {
-                Ok(_) => (),
-                Err(e) => error!("Failed to write data points to influxdb: {:?}", e)
-            };
+    let mut sink = sink::from_config(&config, mqtt.as_ref()).map_err(|e| error!("{}", e))?;
+    sink.check().await.map_err(|e| error!("{}", e))?;
+    info!("Writing readings to {}.", sink.name());
+    let aggregate_sink = match config.aggregate_window_secs {
+        Some(_) if config.aggregate_measurement.is_some() || config.aggregate_bucket.is_some() => {
+            let mut aggregate_config = config.clone();
+            if let Some(measurement) = &config.aggregate_measurement {
+                aggregate_config.influx.single_measurement = true;
+                aggregate_config.influx.measurement = measurement.clone();
+            }
+            if let Some(bucket) = &config.aggregate_bucket {
+                aggregate_config.influx.bucket = Some(bucket.clone());
+            }
+            let mut aggregate_sink =
+                sink::from_config(&aggregate_config, mqtt.as_ref()).map_err(|e| error!("{}", e))?;
+            aggregate_sink.check().await.map_err(|e| error!("{}", e))?;
+            Some(aggregate_sink)
         }
-        sleep(profile_dur).await;
+        _ => None,
+    };
+    let labels: Vec<String> = specs.into_iter().map(|spec| spec.label).collect();
+    let mut collector = Collector::new(&config, sink)
+        .map_err(|e| error!("{}", e))?
+        .with_sensors(&labels);
+    if let Some(aggregate_sink) = aggregate_sink {
+        collector = collector.with_aggregate_sink(aggregate_sink);
+    }
+    if let Some(mqtt) = mqtt {
+        collector = collector.with_mqtt(mqtt);
+    }
+    if let Some(path) = &config.baseline_state_path {
+        collector = collector.with_baseline_state(
+            path.into(),
+            Duration::from_secs(config.baseline_save_interval_secs),
+            Duration::from_secs(config.baseline_max_age_secs),
+        );
+    }
+
+    let latest: SharedReading = Arc::new(RwLock::new(None));
+    if metrics_addr.is_some() || config.otel_endpoint.is_some() {
+        collector = collector.with_latest(latest.clone());
+    }
+    let mut read_requests = None;
+    // Held until the end so a Unix socket is removed on shutdown.
+    let _socket_cleanup = match &metrics_addr {
+        Some(ListenAddr::Unix(path)) => Some(SocketCleanup(path.clone())),
+        _ => None,
+    };
+    if let Some(addr) = metrics_addr {
+        let (requests, receiver) = mpsc::channel(READ_REQUEST_QUEUE);
+        read_requests = Some(receiver);
+        let state = HttpState {
+            latest: latest.clone(),
+            stats: collector.stats(),
+            host: config.host.clone(),
+            read_requests: Some(requests),
+            config: Arc::new(
+                serde_json::to_value(&config)
+                    .map_err(|e| error!("Failed to serialize the configuration: {}", e))?,
+            ),
+        };
+        info!("Serving metrics on {} at /metrics", addr);
+        tokio::spawn(async move {
+            if let Err(e) = http::serve(addr, state).await {
+                error!("Metrics server failed: {}", e);
+            }
+        });
+    }
+
+    // Held until the end so the export keeps running.
+    let _otel = match &config.otel_endpoint {
+        Some(endpoint) => {
+            info!("Exporting OpenTelemetry metrics to {}", endpoint);
+            Some(
+                otel::init(endpoint, &config.host, latest)
+                    .map_err(|e| error!("Failed to set up OpenTelemetry: {}", e))?,
+            )
+        }
+        None => None,
+    };
+
+    if sensor_config.emit_raw {
+        info!(
+            "Raw ADC values are not exposed by the bme680 driver, writing calibration settings instead."
+        );
+        collector
+            .write_metadata(&[
+                (
+                    "config_temperature_offset_c",
+                    sensor_config.temperature_offset as f64,
+                ),
+                (
+                    "config_gas_heater_temp_c",
+                    sensor_config.gas_heater_temp_c as f64,
+                ),
+                (
+                    "config_gas_heater_duration_ms",
+                    sensor_config.gas_heater_duration_ms as f64,
+                ),
+                ("config_ambient_temp_c", sensor_config.ambient_temp_c as f64),
+                (
+                    "config_measure_gas",
+                    if sensor_config.measure_gas { 1.0 } else { 0.0 },
+                ),
+            ])
+            .await;
+    }
+    if let Some((latitude, longitude)) = coordinates {
+        collector.write_location(latitude, longitude).await;
+    }
+
+    if run_mode == RunMode::Once {
+        // One profile duration is enough for a valid reading, STABILIZE_SECS=0 skips even that.
+        if config.stabilize_secs > 0 {
+            sleep(profile_dur).await;
+        }
+        return match collector::run_once(&mut sensors, &mut collector, profile_dur).await {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                error!("Single reading failed, {}", e);
+                std::process::exit(e.exit_code());
+            }
+        };
+    }
+
+    if config.startup_selftest {
+        collector::self_test(&mut sensors, &mut collector, config.startup_selftest_write)
+            .await
+            .map_err(|e| error!("Startup self-test failed: {}", e))?;
+    }
+
+    let mut sigterm = signal(SignalKind::terminate())
+        .map_err(|e| error!("Failed to install SIGTERM handler: {:?}", e))?;
+
+    let stabilize = stabilize_duration(Duration::from_secs(config.stabilize_secs), profile_dur);
+    if stabilize_mode == StabilizeMode::Auto && !sensor_config.measure_gas {
+        warn!("STABILIZE_MODE auto needs gas measurement, waiting STABILIZE_SECS instead.");
+    }
+    if stabilize_mode == StabilizeMode::Auto && sensor_config.measure_gas {
+        let settle = Duration::from_secs(config.stabilize_settle_secs);
+        let max_wait = Duration::from_secs(config.stabilize_max_secs);
+        info!(
+            "Waiting for the gas resistance to stay within {}% for {:?}, at most {:?}.",
+            config.stabilize_delta_pct, settle, max_wait
+        );
+        tokio::select! {
+            settled = collector::wait_for_gas_stability(
+                &mut sensors,
+                GasStability::new(config.stabilize_delta_pct, settle),
+                sensor_config.gas_heater_temp_c,
+                read_interval,
+                max_wait,
+            ) => {
+                if !settled {
+                    warn!("Gas resistance didn't settle within {:?}, reading anyway.", max_wait);
+                }
+            }
+            _ = wait_for_shutdown(&mut sigterm) => {
+                info!("Shutting down before the first reading.");
+                return Ok(());
+            }
+        }
+    } else if stabilize > Duration::from_secs(0) {
+        info!(
+            "Waiting {:?} for device to stabilize before reading.",
+            stabilize
+        );
+        tokio::select! {
+            _ = sleep(stabilize) => {}
+            _ = wait_for_shutdown(&mut sigterm) => {
+                info!("Shutting down before the first reading.");
+                return Ok(());
+            }
+        }
+    } else {
+        info!("Skipping stabilization wait.");
+    }
+    info!("Starting readings.");
+    collector
+        .write_event(
+            None,
+            EventKind::Stabilized,
+            "Stabilization finished, starting readings",
+        )
+        .await;
+
+    let quiet_hours = match &config.quiet_hours {
+        Some(window) => Some(
+            parse_daily_window(window).map_err(|e| error!("Failed to load QUIET_HOURS: {}", e))?,
+        ),
+        None => None,
+    };
+    if quiet_hours.is_some() && !sensor_config.measure_gas {
+        warn!("QUIET_HOURS has no effect with MEASURE_GAS off.");
+    }
+    let loop_settings = LoopSettings {
+        read_interval,
+        max_consecutive_read_errors: config.max_consecutive_read_errors,
+        reinit_retry: config.reinit_retry.policy(),
+        samples_per_point: config.samples_per_point,
+        max_runtime: config.max_runtime_secs.map(Duration::from_secs),
+        quiet_hours,
+        write_jitter: config.write_jitter_ms.map(Duration::from_millis),
+    };
+    if let Some(max_runtime) = loop_settings.max_runtime {
+        info!(
+            "Exiting after {:?} of readings for a scheduled restart.",
+            max_runtime
+        );
+    }
+    let result = collector::run(
+        &mut sensors,
+        &mut collector,
+        loop_settings,
+        read_requests,
+        wait_for_shutdown(&mut sigterm),
+    )
+    .await;
+    if let Err(e) = &result {
+        error!("Giving up, no sensor could be re-initialized: {}", e);
+    }
+
+    collector
+        .flush()
+        .await
+        .map_err(|e| error!("Failed to flush {}: {}", collector.sink().name(), e))?;
+    result.map_err(|_| ())
+}
+
+/// Import a file written by the CSV sink into the configured outputs.
+async fn run_backfill(path: &Path, config: &Config) -> Result<(), ()> {
+    let mut config = config.clone();
+    config.batch_size = config.batch_size.max(BACKFILL_BATCH_SIZE);
+    let mut sink = sink::from_config(&config, None).map_err(|e| error!("{}", e))?;
+    sink.check().await.map_err(|e| error!("{}", e))?;
+    let mut tag_columns: BTreeSet<String> = TAG_COLUMNS
+        .iter()
+        .map(|column| column.to_string())
+        .collect();
+    tag_columns.extend(config.tags.keys().cloned());
+    let imported = backfill(path, sink.as_mut(), &tag_columns)
+        .await
+        .map_err(|e| error!("Backfill failed: {}", e))?;
+    info!("Imported {} readings from {}.", imported, path.display());
+    Ok(())
+}
+
+/// Resolves on SIGINT or SIGTERM.
+async fn wait_for_shutdown(sigterm: &mut Signal) {
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = sigterm.recv() => {}
     }
 }
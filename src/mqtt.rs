@@ -0,0 +1,209 @@
+//! Optional MQTT publisher with Home Assistant discovery support.
+
+use crate::config::MqttConfig;
+use crate::reading::Reading;
+use crate::retry::RetryPolicy;
+use rumqttc::{AsyncClient, ClientError, Event, EventLoop, MqttOptions, Packet, QoS};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+const DEFAULT_PORT: u16 = 1883;
+
+/// Publishes queued while the broker is unreachable, the oldest are sent first on reconnect.
+const QUEUE_CAPACITY: usize = 256;
+
+/// First delay between reconnect attempts, doubled after every failure.
+const RECONNECT_BASE_DELAY: Duration = Duration::from_secs(1);
+
+/// Cloning shares the connection.
+#[derive(Clone)]
+pub struct MqttPublisher {
+    client: AsyncClient,
+    host: String,
+    topic_prefix: String,
+    state_topic: String,
+}
+
+/// Split `host[:port]`, using the standard MQTT port when none is given.
+pub fn parse_broker(broker: &str) -> Result<(String, u16), String> {
+    match broker.rsplit_once(':') {
+        Some((host, port)) => port
+            .parse()
+            .map(|port| (host.to_string(), port))
+            .map_err(|e| format!("invalid port in {}: {}", broker, e)),
+        None => Ok((broker.to_string(), DEFAULT_PORT)),
+    }
+}
+
+impl MqttPublisher {
+    /// Create the client and spawn its event loop, which reconnects with backoff when the broker
+    /// goes away.
+    pub fn connect(
+        broker: &str,
+        config: &MqttConfig,
+        host: &str,
+        measure_gas: bool,
+    ) -> Result<Self, String> {
+        let (broker_host, broker_port) = parse_broker(broker)?;
+        let options = MqttOptions::new(format!("atmosphere-{}", host), broker_host, broker_port);
+        let (client, eventloop) = AsyncClient::new(options, QUEUE_CAPACITY);
+        let max_backoff = Duration::from_secs(config.reconnect_max_backoff_secs);
+        tokio::spawn(drive(eventloop, max_backoff));
+
+        let publisher = MqttPublisher {
+            client,
+            host: host.to_string(),
+            topic_prefix: config.topic_prefix.clone(),
+            state_topic: format!("{}/{}/state", config.topic_prefix, host),
+        };
+        if config.discovery {
+            publisher.publish_discovery(&config.discovery_prefix, measure_gas)?;
+        }
+        Ok(publisher)
+    }
+
+    /// Topic that full records are published to by the MQTT sink.
+    pub fn record_topic(&self) -> String {
+        format!("{}/{}/record", self.topic_prefix, self.host)
+    }
+
+    /// Queue a publish without waiting, so a broker that is down can't stall the read loop.
+    /// Fails once the queue is full.
+    pub fn publish_json(&self, topic: &str, payload: &Value) -> Result<(), ClientError> {
+        self.client.try_publish(
+            topic,
+            QoS::AtLeastOnce,
+            false,
+            payload.to_string().into_bytes(),
+        )
+    }
+
+    pub fn publish_reading(&self, reading: &Reading) -> Result<(), ClientError> {
+        let mut payload = json!({
+            "timestamp": reading.timestamp,
+            "temperature": reading.temperature_c,
+            "humidity": reading.humidity_percent,
+            "pressure": reading.pressure_hpa,
+        });
+        if let Some(gas_ohms) = reading.gas_ohms {
+            payload["gas_resistance"] = json!(gas_ohms);
+        }
+        self.publish_json(&self.state_topic, &payload)
+    }
+
+    /// Queue retained discovery configs so Home Assistant registers each sensor.
+    fn publish_discovery(&self, prefix: &str, measure_gas: bool) -> Result<(), String> {
+        let mut sensors = vec![
+            ("temperature", "Temperature", Some("temperature"), "°C"),
+            ("humidity", "Humidity", Some("humidity"), "%"),
+            ("pressure", "Pressure", Some("pressure"), "hPa"),
+        ];
+        if measure_gas {
+            sensors.push(("gas_resistance", "Gas Resistance", None, "Ω"));
+        }
+        for (key, name, device_class, unit) in sensors.iter() {
+            let unique_id = format!("atmosphere_{}_{}", self.host, key);
+            let mut config = json!({
+                "name": format!("{} {}", self.host, name),
+                "unique_id": unique_id,
+                "state_topic": self.state_topic,
+                "unit_of_measurement": unit,
+                "value_template": format!("{{{{ value_json.{} }}}}", key),
+                "device": {
+                    "identifiers": [format!("atmosphere_{}", self.host)],
+                    "name": format!("atmosphere {}", self.host),
+                    "model": "BME680",
+                },
+            });
+            if let Some(device_class) = device_class {
+                config["device_class"] = json!(device_class);
+            }
+            let topic = format!("{}/sensor/{}/config", prefix, unique_id);
+            self.client
+                .try_publish(
+                    topic,
+                    QoS::AtLeastOnce,
+                    true,
+                    config.to_string().into_bytes(),
+                )
+                .map_err(|e| format!("failed to queue discovery config: {:?}", e))?;
+        }
+        info!(
+            "Queued Home Assistant discovery for {} sensors.",
+            sensors.len()
+        );
+        Ok(())
+    }
+}
+
+/// Delay before reconnect attempt number `attempt` (0-based), doubling from one second up to
+/// `max_backoff`.
+pub fn reconnect_backoff(attempt: u32, max_backoff: Duration) -> Duration {
+    let policy = RetryPolicy {
+        retries: u32::MAX,
+        base_delay: RECONNECT_BASE_DELAY.min(max_backoff),
+        max_delay: max_backoff,
+    };
+    policy.delay_for_retry(attempt).unwrap_or(max_backoff)
+}
+
+/// Poll the event loop forever. rumqttc reconnects on the next poll after an error, so failed
+/// polls are spaced out with [`reconnect_backoff`] until the broker accepts us again.
+async fn drive(mut eventloop: EventLoop, max_backoff: Duration) {
+    let mut connected = false;
+    let mut attempt = 0;
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                if attempt > 0 {
+                    info!("MQTT reconnected after {} attempts.", attempt);
+                } else {
+                    info!("MQTT connected.");
+                }
+                connected = true;
+                attempt = 0;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                let delay = reconnect_backoff(attempt, max_backoff);
+                if connected {
+                    warn!("MQTT connection lost, reconnecting in {:?}: {:?}", delay, e);
+                    connected = false;
+                } else {
+                    warn!(
+                        "MQTT connection attempt {} failed, retrying in {:?}: {:?}",
+                        attempt + 1,
+                        delay,
+                        e
+                    );
+                }
+                attempt = attempt.saturating_add(1);
+                sleep(delay).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reconnect_backoff_doubles_up_to_the_maximum() {
+        let max = Duration::from_secs(30);
+        let delays: Vec<u64> = (0..7)
+            .map(|attempt| reconnect_backoff(attempt, max).as_secs())
+            .collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 30, 30]);
+        assert_eq!(reconnect_backoff(u32::MAX - 1, max), max);
+    }
+
+    #[test]
+    fn reconnect_backoff_below_a_second_stays_at_the_maximum() {
+        let max = Duration::from_millis(200);
+        assert_eq!(reconnect_backoff(0, max), max);
+        assert_eq!(reconnect_backoff(5, max), max);
+    }
+}
@@ -0,0 +1,103 @@
+//! Optional OpenTelemetry metrics export of the latest reading over OTLP, as an alternative to
+//! scraping the metrics endpoint.
+
+use crate::http::SharedReading;
+use crate::reading::Reading;
+use opentelemetry::metrics::{MeterProvider, ObserverResult, Unit, ValueObserver};
+use opentelemetry::sdk::metrics::{controllers::PushController, selectors};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+
+/// A gauge's name, description, unit and the value it takes from a reading.
+type Gauge = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&Reading) -> Option<f64>,
+);
+
+/// Keeps the export pipeline and its gauges alive; exporting stops when this is dropped.
+pub struct OtelMetrics {
+    _controller: PushController,
+    _gauges: Vec<ValueObserver<f64>>,
+}
+
+/// Start exporting the reading in `latest` as gauges to the OTLP collector at `endpoint`,
+/// with `host` as a resource attribute. Gauges are left out until the first reading.
+///
+/// Must be called from within the Tokio runtime, which the export runs on.
+pub fn init(endpoint: &str, host: &str, latest: SharedReading) -> Result<OtelMetrics, String> {
+    let controller = opentelemetry_otlp::new_pipeline()
+        .metrics(tokio::spawn, opentelemetry::util::tokio_interval_stream)
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_aggregator_selector(selectors::simple::Selector::Exact)
+        .with_resource(vec![
+            KeyValue::new("service.name", "atmosphere"),
+            KeyValue::new("host.name", host.to_string()),
+        ])
+        .build()
+        .map_err(|e| format!("failed to build the OTLP metrics pipeline: {}", e))?;
+    let meter = controller.provider().meter("atmosphere", None);
+
+    let gauges: [Gauge; 4] = [
+        ("bme680.temperature", "Temperature.", "Cel", |reading| {
+            Some(reading.temperature_c)
+        }),
+        ("bme680.humidity", "Relative humidity.", "%", |reading| {
+            Some(reading.humidity_percent)
+        }),
+        ("bme680.pressure", "Station pressure.", "hPa", |reading| {
+            Some(reading.pressure_hpa)
+        }),
+        (
+            "bme680.gas_resistance",
+            "Gas sensor resistance.",
+            "Ohm",
+            |reading| reading.gas_ohms,
+        ),
+    ];
+    let gauges = gauges
+        .iter()
+        .map(|&(name, description, unit, value)| {
+            let latest = latest.clone();
+            meter
+                .f64_value_observer(name, move |result: ObserverResult<f64>| {
+                    // Observers can't wait, a reading being written is picked up next time.
+                    let reading = match latest.try_read() {
+                        Ok(reading) => *reading,
+                        Err(_) => return,
+                    };
+                    if let Some(value) = reading.as_ref().and_then(value) {
+                        result.observe(value, &[]);
+                    }
+                })
+                .with_description(description)
+                .with_unit(Unit::new(unit))
+                .init()
+        })
+        .collect();
+    Ok(OtelMetrics {
+        _controller: controller,
+        _gauges: gauges,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    #[tokio::test]
+    async fn pipeline_and_gauges_construct_without_a_collector() {
+        // Nothing listens on the endpoint, exports fail in the background without affecting
+        // the setup.
+        let latest: SharedReading = Arc::new(RwLock::new(None));
+        let metrics = init("http://127.0.0.1:4317", "test", latest).unwrap();
+        assert_eq!(metrics._gauges.len(), 4);
+    }
+}
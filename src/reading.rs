@@ -0,0 +1,322 @@
+//! The values produced by a single sensor reading.
+
+use crate::units::{GasUnit, PressureUnit, TemperatureUnit};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Compensated values from one BME680 reading, after smoothing. Values are always stored in °C,
+/// hPa and ohms; outputs convert through the `*_in` accessors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reading {
+    /// Nanoseconds since the Unix epoch.
+    pub timestamp: i64,
+    pub temperature_c: f64,
+    pub humidity_percent: f64,
+    pub pressure_hpa: f64,
+    /// `None` when gas measurement is disabled.
+    pub gas_ohms: Option<f64>,
+    /// Heater temperature of this gas measurement, set when cycling a heater profile.
+    pub heater_temp_c: Option<u16>,
+}
+
+impl Reading {
+    pub fn temperature_in(&self, unit: TemperatureUnit) -> f64 {
+        unit.convert(self.temperature_c)
+    }
+
+    pub fn pressure_in(&self, unit: PressureUnit) -> f64 {
+        unit.convert(self.pressure_hpa)
+    }
+
+    pub fn gas_in(&self, unit: GasUnit) -> Option<f64> {
+        self.gas_ohms.map(|ohms| unit.convert(ohms))
+    }
+}
+
+/// The mean of `readings`, timestamped like the last one, or `None` when there are none. Gas
+/// resistance is only averaged over the readings at the heater temperature of the last one,
+/// since resistances at different temperatures aren't comparable.
+pub fn average_readings(readings: &[Reading]) -> Option<Reading> {
+    let last = *readings.last()?;
+    let mean = |value: fn(&Reading) -> f64| {
+        readings.iter().map(value).sum::<f64>() / readings.len() as f64
+    };
+    let gas: Vec<f64> = readings
+        .iter()
+        .filter(|reading| reading.heater_temp_c == last.heater_temp_c)
+        .filter_map(|reading| reading.gas_ohms)
+        .collect();
+    Some(Reading {
+        timestamp: last.timestamp,
+        temperature_c: mean(|reading| reading.temperature_c),
+        humidity_percent: mean(|reading| reading.humidity_percent),
+        pressure_hpa: mean(|reading| reading.pressure_hpa),
+        gas_ohms: if gas.is_empty() {
+            None
+        } else {
+            Some(gas.iter().sum::<f64>() / gas.len() as f64)
+        },
+        heater_temp_c: last.heater_temp_c,
+    })
+}
+
+/// A single named value written for a reading.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub measurement: String,
+    pub value: f64,
+    /// Tags that only apply to this field, on top of the record's.
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Everything emitted for one reading: the raw values and derived ones, sharing tags and a
+/// timestamp.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// Nanoseconds since the Unix epoch.
+    pub timestamp: i64,
+    pub tags: BTreeMap<String, String>,
+    pub fields: Vec<Field>,
+    /// String fields, such as the message of an event. Sinks without string values skip them.
+    pub text: BTreeMap<String, String>,
+    /// Measurement all fields go to as one point, regardless of the sink's layout.
+    pub measurement: Option<String>,
+}
+
+impl Field {
+    pub fn new(measurement: &str, value: f64) -> Self {
+        Field {
+            measurement: measurement.to_string(),
+            value,
+            tags: BTreeMap::new(),
+        }
+    }
+}
+
+impl Record {
+    pub fn new(timestamp: i64, tags: BTreeMap<String, String>) -> Self {
+        Record {
+            timestamp,
+            tags,
+            fields: Vec::new(),
+            text: BTreeMap::new(),
+            measurement: None,
+        }
+    }
+
+    pub fn push(&mut self, measurement: &str, value: f64) {
+        self.push_tagged(measurement, value, BTreeMap::new());
+    }
+
+    pub fn push_tagged(&mut self, measurement: &str, value: f64, tags: BTreeMap<String, String>) {
+        self.fields.push(Field {
+            measurement: measurement.to_string(),
+            value,
+            tags,
+        });
+    }
+}
+
+/// Nanoseconds since the Unix epoch, the default InfluxDB timestamp precision.
+pub fn unix_nanos(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_nanos() as i64)
+        .unwrap_or(0)
+}
+
+/// 2023-01-01T00:00:00Z. A clock reading earlier than this hasn't been set yet, e.g. on a Pi
+/// without an RTC before NTP synced.
+const MIN_PLAUSIBLE_UNIX_SECS: u64 = 1_672_531_200;
+
+/// Whether `time` looks like it came from a clock that has been set.
+pub fn clock_is_plausible(time: SystemTime) -> bool {
+    time.duration_since(UNIX_EPOCH)
+        .is_ok_and(|since_epoch| since_epoch.as_secs() >= MIN_PLAUSIBLE_UNIX_SECS)
+}
+
+/// Plausible bounds for raw values, inclusive. Readings outside them are discarded as garbage,
+/// such as the zeros the sensor sometimes reports right after being re-initialized.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidRanges {
+    pub temp_min_c: f64,
+    pub temp_max_c: f64,
+    pub humidity_min_percent: f64,
+    pub humidity_max_percent: f64,
+    pub pressure_min_hpa: f64,
+    pub pressure_max_hpa: f64,
+}
+
+impl Default for ValidRanges {
+    /// The BME680's operating range. Exactly 0% humidity is rejected since it only shows up in
+    /// bad readings.
+    fn default() -> Self {
+        ValidRanges {
+            temp_min_c: -40.0,
+            temp_max_c: 85.0,
+            humidity_min_percent: 0.1,
+            humidity_max_percent: 100.0,
+            pressure_min_hpa: 300.0,
+            pressure_max_hpa: 1100.0,
+        }
+    }
+}
+
+/// Check a reading against `ranges`, describing the first value out of range.
+pub fn validate_reading(reading: &Reading, ranges: &ValidRanges) -> Result<(), String> {
+    let checks = [
+        (
+            "temperature",
+            reading.temperature_c,
+            ranges.temp_min_c,
+            ranges.temp_max_c,
+        ),
+        (
+            "humidity",
+            reading.humidity_percent,
+            ranges.humidity_min_percent,
+            ranges.humidity_max_percent,
+        ),
+        (
+            "pressure",
+            reading.pressure_hpa,
+            ranges.pressure_min_hpa,
+            ranges.pressure_max_hpa,
+        ),
+    ];
+    for (name, value, min, max) in checks.iter() {
+        if !(*min..=*max).contains(value) {
+            return Err(format!("{} of {} is outside {}..{}", name, value, min, max));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn unix_nanos_counts_nanoseconds_since_the_epoch() {
+        assert_eq!(unix_nanos(UNIX_EPOCH), 0);
+        let time = UNIX_EPOCH + Duration::new(1_600_000_000, 123_456_789);
+        assert_eq!(unix_nanos(time), 1_600_000_000_123_456_789);
+    }
+
+    #[test]
+    fn unix_nanos_before_the_epoch_is_zero() {
+        assert_eq!(unix_nanos(UNIX_EPOCH - Duration::from_secs(1)), 0);
+    }
+
+    fn reading() -> Reading {
+        Reading {
+            timestamp: 1_700_000_000_000_000_000,
+            temperature_c: 20.0,
+            humidity_percent: 45.0,
+            pressure_hpa: 1013.25,
+            gas_ohms: Some(50_000.0),
+            heater_temp_c: None,
+        }
+    }
+
+    #[test]
+    fn reading_converts_through_its_accessors() {
+        let reading = reading();
+        assert_eq!(reading.temperature_in(TemperatureUnit::Celsius), 20.0);
+        assert_eq!(reading.temperature_in(TemperatureUnit::Fahrenheit), 68.0);
+        assert_eq!(reading.pressure_in(PressureUnit::Hectopascal), 1013.25);
+        assert_eq!(reading.gas_in(GasUnit::Kilohm), Some(50.0));
+        let without_gas = Reading {
+            gas_ohms: None,
+            ..reading
+        };
+        assert_eq!(without_gas.gas_in(GasUnit::Ohm), None);
+    }
+
+    #[test]
+    fn record_fields_keep_their_order() {
+        let mut record = Record::new(reading().timestamp, BTreeMap::new());
+        record.push("temperature_c", 20.0);
+        record.push("relative_humidity", 45.0);
+        let names: Vec<&str> = record
+            .fields
+            .iter()
+            .map(|field| field.measurement.as_str())
+            .collect();
+        assert_eq!(names, vec!["temperature_c", "relative_humidity"]);
+    }
+
+    #[test]
+    fn plausible_readings_pass_validation() {
+        let ranges = ValidRanges::default();
+        assert_eq!(validate_reading(&reading(), &ranges), Ok(()));
+        let at_limits = Reading {
+            temperature_c: -40.0,
+            humidity_percent: 100.0,
+            pressure_hpa: 1100.0,
+            ..reading()
+        };
+        assert_eq!(validate_reading(&at_limits, &ranges), Ok(()));
+    }
+
+    #[test]
+    fn impossible_readings_fail_validation() {
+        let ranges = ValidRanges::default();
+        let zero_humidity = Reading {
+            humidity_percent: 0.0,
+            ..reading()
+        };
+        assert!(validate_reading(&zero_humidity, &ranges)
+            .unwrap_err()
+            .starts_with("humidity"));
+        let zero_pressure = Reading {
+            pressure_hpa: 0.0,
+            ..reading()
+        };
+        assert!(validate_reading(&zero_pressure, &ranges)
+            .unwrap_err()
+            .starts_with("pressure"));
+        let too_hot = Reading {
+            temperature_c: 85.1,
+            ..reading()
+        };
+        assert!(validate_reading(&too_hot, &ranges).is_err());
+        let not_a_number = Reading {
+            temperature_c: f64::NAN,
+            ..reading()
+        };
+        assert!(validate_reading(&not_a_number, &ranges).is_err());
+    }
+
+    #[test]
+    fn accessors_convert_to_every_unit() {
+        let reading = Reading {
+            temperature_c: -10.0,
+            pressure_hpa: 1000.0,
+            ..reading()
+        };
+        assert_eq!(reading.temperature_in(TemperatureUnit::Celsius), -10.0);
+        assert_eq!(reading.temperature_in(TemperatureUnit::Fahrenheit), 14.0);
+        assert_eq!(reading.pressure_in(PressureUnit::Hectopascal), 1000.0);
+        assert!((reading.pressure_in(PressureUnit::InchesOfMercury) - 29.53).abs() < 0.005);
+        assert!((reading.pressure_in(PressureUnit::MillimetresOfMercury) - 750.06).abs() < 0.005);
+        assert_eq!(reading.gas_in(GasUnit::Ohm), Some(50_000.0));
+        assert_eq!(reading.gas_in(GasUnit::Kilohm), Some(50.0));
+        // The stored values stay metric.
+        assert_eq!(reading.temperature_c, -10.0);
+    }
+
+    #[test]
+    fn clock_is_plausible_from_2023_on() {
+        assert!(!clock_is_plausible(UNIX_EPOCH));
+        assert!(!clock_is_plausible(
+            UNIX_EPOCH + Duration::from_secs(1_672_531_199)
+        ));
+        assert!(clock_is_plausible(
+            UNIX_EPOCH + Duration::from_secs(1_672_531_200)
+        ));
+        assert!(clock_is_plausible(SystemTime::now()));
+    }
+}
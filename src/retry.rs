@@ -0,0 +1,134 @@
+//! Retrying fallible async operations with exponential backoff.
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::warn;
+
+/// How often and how patiently to retry an operation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt, so the operation runs at most `retries + 1` times.
+    pub retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry` (0-based), doubling from `base_delay` up to
+    /// `max_delay`, or `None` once the retries are used up.
+    pub fn delay_for_retry(&self, retry: u32) -> Option<Duration> {
+        if retry >= self.retries {
+            return None;
+        }
+        let factor = 2u32.checked_pow(retry).unwrap_or(u32::MAX);
+        let delay = self
+            .base_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay);
+        Some(delay.min(self.max_delay))
+    }
+}
+
+/// Run `op` until it succeeds or the policy gives up, logging each retry at `warn`.
+pub async fn retry<T, E, F, Fut>(policy: &RetryPolicy, what: &str, mut op: F) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: Debug,
+{
+    let mut retry = 0;
+    loop {
+        match op().await {
+            Ok(value) => return Ok(value),
+            Err(e) => match policy.delay_for_retry(retry) {
+                Some(delay) => {
+                    warn!(
+                        "{} failed, retry {}/{} in {:?}: {:?}",
+                        what,
+                        retry + 1,
+                        policy.retries,
+                        delay,
+                        e
+                    );
+                    sleep(delay).await;
+                    retry += 1;
+                }
+                None => return Err(e),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn policy(retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            retries,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+        }
+    }
+
+    #[test]
+    fn delays_double_up_to_the_maximum() {
+        let delays: Vec<_> = (0..6)
+            .map(|retry| policy(6).delay_for_retry(retry))
+            .collect();
+        let ms = |ms| Some(Duration::from_millis(ms));
+        assert_eq!(
+            delays,
+            vec![ms(100), ms(200), ms(400), ms(800), ms(1000), ms(1000)]
+        );
+    }
+
+    #[test]
+    fn no_delay_once_retries_are_used_up() {
+        assert_eq!(policy(2).delay_for_retry(2), None);
+        assert_eq!(policy(0).delay_for_retry(0), None);
+    }
+
+    #[test]
+    fn huge_retry_numbers_stay_at_the_maximum() {
+        assert_eq!(
+            policy(u32::MAX).delay_for_retry(40),
+            Some(Duration::from_millis(1000))
+        );
+    }
+
+    #[tokio::test]
+    async fn retry_gives_up_after_the_retries() {
+        let policy = RetryPolicy {
+            retries: 2,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+        };
+        let attempts = Cell::new(0);
+        let result: Result<(), &str> = retry(&policy, "test", || {
+            attempts.set(attempts.get() + 1);
+            async { Err("down") }
+        })
+        .await;
+        assert_eq!(result, Err("down"));
+        assert_eq!(attempts.get(), 3);
+
+        attempts.set(0);
+        let result = retry(&policy, "test", || {
+            attempts.set(attempts.get() + 1);
+            let attempt = attempts.get();
+            async move {
+                if attempt < 2 {
+                    Err("down")
+                } else {
+                    Ok(attempt)
+                }
+            }
+        })
+        .await;
+        assert_eq!(result, Ok(2));
+    }
+}
@@ -0,0 +1,105 @@
+//! Daily windows of local time, like the quiet hours the gas heater is off during.
+
+use chrono::Timelike;
+
+/// A daily window from `start` up to but excluding `end`, in minutes since midnight. Windows
+/// ending before they start run past midnight.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DailyWindow {
+    start: u32,
+    end: u32,
+}
+
+impl DailyWindow {
+    /// Whether `minute_of_day`, in minutes since midnight, falls into the window.
+    pub fn contains(&self, minute_of_day: u32) -> bool {
+        if self.start <= self.end {
+            minute_of_day >= self.start && minute_of_day < self.end
+        } else {
+            minute_of_day >= self.start || minute_of_day < self.end
+        }
+    }
+}
+
+/// Parse a window like `22:00-06:00`.
+pub fn parse_daily_window(s: &str) -> Result<DailyWindow, String> {
+    let (start, end) = s
+        .split_once('-')
+        .ok_or_else(|| format!("expected HH:MM-HH:MM, got {:?}", s))?;
+    let window = DailyWindow {
+        start: parse_time_of_day(start)?,
+        end: parse_time_of_day(end)?,
+    };
+    if window.start == window.end {
+        return Err(format!("{:?} starts and ends at the same time", s));
+    }
+    Ok(window)
+}
+
+/// Minutes since midnight of an `HH:MM` time.
+fn parse_time_of_day(s: &str) -> Result<u32, String> {
+    let s = s.trim();
+    let (hours, minutes) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected HH:MM, got {:?}", s))?;
+    let hours: u32 = hours
+        .parse()
+        .map_err(|_| format!("invalid hour in {:?}", s))?;
+    let minutes: u32 = minutes
+        .parse()
+        .map_err(|_| format!("invalid minute in {:?}", s))?;
+    if hours >= 24 || minutes >= 60 {
+        return Err(format!("{:?} is not a time of day", s));
+    }
+    Ok(hours * 60 + minutes)
+}
+
+/// Minutes since midnight of `time`.
+pub fn minute_of_day<T: Timelike>(time: &T) -> u32 {
+    time.hour() * 60 + time.minute()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveTime;
+
+    fn at(time: &str) -> u32 {
+        minute_of_day(&NaiveTime::parse_from_str(time, "%H:%M").unwrap())
+    }
+
+    #[test]
+    fn windows_within_a_day() {
+        let window = parse_daily_window("09:30-17:00").unwrap();
+        assert!(!window.contains(at("09:29")));
+        assert!(window.contains(at("09:30")));
+        assert!(window.contains(at("16:59")));
+        assert!(!window.contains(at("17:00")));
+    }
+
+    #[test]
+    fn windows_crossing_midnight() {
+        let window = parse_daily_window(" 22:00 - 06:00 ").unwrap();
+        assert!(window.contains(at("22:00")));
+        assert!(window.contains(at("23:59")));
+        assert!(window.contains(at("00:00")));
+        assert!(window.contains(at("05:59")));
+        assert!(!window.contains(at("06:00")));
+        assert!(!window.contains(at("12:00")));
+        assert!(!window.contains(at("21:59")));
+    }
+
+    #[test]
+    fn malformed_windows_are_errors() {
+        for value in &[
+            "22:00",
+            "22-06",
+            "24:00-06:00",
+            "22:60-06:00",
+            "06:00-06:00",
+            "",
+        ] {
+            assert!(parse_daily_window(value).is_err(), "{:?} parsed", value);
+        }
+    }
+}
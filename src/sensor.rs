@@ -0,0 +1,776 @@
+//! The BME680 sensor and its lifecycle helpers.
+
+use crate::reading::{average_readings, unix_nanos, Reading};
+use bme680::{Bme680, FieldData, FieldDataCondition, I2CAddress, PowerMode, Settings};
+use i2cdev::core::I2CDevice;
+use i2cdev::linux::{LinuxI2CDevice, LinuxI2CError};
+use linux_embedded_hal::{Delay, I2cdev};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tracing::{debug, warn};
+
+#[derive(Debug)]
+pub enum SensorError {
+    /// Opening the I2C bus or initializing the sensor failed.
+    Init(String),
+    /// Triggering or fetching a measurement failed.
+    Read(String),
+    /// The measurement finished without new data, so there is nothing to report this cycle.
+    NoNewData,
+}
+
+impl fmt::Display for SensorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SensorError::Init(e) => write!(f, "failed to initialize sensor: {}", e),
+            SensorError::Read(e) => write!(f, "failed to read sensor: {}", e),
+            SensorError::NoNewData => f.write_str("sensor returned no new data"),
+        }
+    }
+}
+
+/// Attempts at a measurement while the bus reports transient errors, before it counts as failed.
+const BUS_ATTEMPTS: u32 = 3;
+const BUS_RETRY_DELAY: Duration = Duration::from_millis(20);
+
+// Linux errno values the I2C driver reports for a busy bus.
+const EINTR: i32 = 4;
+const EAGAIN: i32 = 11;
+const EBUSY: i32 = 16;
+const ETIMEDOUT: i32 = 110;
+
+/// Whether an error is likely to clear up on its own.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCategory {
+    /// The bus was busy or arbitration was lost, worth retrying right away.
+    Transient,
+    /// Anything else, e.g. the sensor doesn't respond or returned garbage.
+    Persistent,
+}
+
+impl ErrorCategory {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCategory::Transient => "transient",
+            ErrorCategory::Persistent => "persistent",
+        }
+    }
+}
+
+/// Categorize an errno from the I2C driver. Arbitration lost shows up as `EAGAIN`.
+pub fn categorize_errno(errno: i32) -> ErrorCategory {
+    match errno {
+        EINTR | EAGAIN | EBUSY | ETIMEDOUT => ErrorCategory::Transient,
+        _ => ErrorCategory::Persistent,
+    }
+}
+
+/// Categorize a driver error, describing it. Only bus errors carry an errno, everything else
+/// the driver reports is persistent.
+fn categorize(e: bme680::Error<LinuxI2CError, LinuxI2CError>) -> (ErrorCategory, String) {
+    match e {
+        bme680::Error::I2CRead(e) | bme680::Error::I2CWrite(e) => {
+            let e = io::Error::from(e);
+            let category = e
+                .raw_os_error()
+                .map_or(ErrorCategory::Persistent, categorize_errno);
+            (category, format!("I2C error: {}", e))
+        }
+        other => (ErrorCategory::Persistent, format!("{:?}", other)),
+    }
+}
+
+/// Run blocking sensor I/O from async code, such as a read that waits out the measurement and
+/// the short retries of a busy bus in the calling thread. The worker's other tasks, like sink
+/// writes and the HTTP server, are handed to other threads meanwhile. The sensors are borrowed
+/// by the read loop rather than owned, so they can't be moved to `spawn_blocking` instead.
+///
+/// # Panics
+///
+/// Outside a multi-threaded runtime, e.g. in a `#[tokio::test]` without
+/// `flavor = "multi_thread"`, as `block_in_place` can't hand the other tasks off there.
+pub fn blocking_io<T>(io: impl FnOnce() -> T) -> T {
+    let multi_thread = Handle::try_current()
+        .is_ok_and(|handle| handle.runtime_flavor() == RuntimeFlavor::MultiThread);
+    assert!(
+        multi_thread,
+        "sensor I/O needs a multi-threaded tokio runtime, \
+         use #[tokio::main] or #[tokio::test(flavor = \"multi_thread\")]"
+    );
+    tokio::task::block_in_place(io)
+}
+
+/// Register with the low bits of the gas resistance, and whether the gas measurement is valid
+/// and the heater reached its target. The bme680 driver reads but doesn't expose these bits.
+const GAS_R_LSB_REGISTER: u8 = 0x2B;
+const GAS_VALID_MASK: u8 = 0x20;
+const HEAT_STAB_MASK: u8 = 0x10;
+
+/// Validity bits of the last gas measurement.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GasStatus {
+    pub valid: bool,
+    pub heat_stable: bool,
+}
+
+impl GasStatus {
+    /// The bits of a `gas_r_lsb` register value.
+    pub fn from_register(value: u8) -> Self {
+        GasStatus {
+            valid: value & GAS_VALID_MASK != 0,
+            heat_stable: value & HEAT_STAB_MASK != 0,
+        }
+    }
+
+    /// Whether the gas resistance can be trusted.
+    pub fn is_usable(self) -> bool {
+        self.valid && self.heat_stable
+    }
+}
+
+/// Pause between the reads of a burst, letting the gas heater cool down from the last one.
+const BURST_SETTLE: Duration = Duration::from_millis(100);
+
+/// Something that produces environmental readings on demand.
+pub trait EnvSensor {
+    fn read(&mut self) -> Result<Reading, SensorError>;
+
+    /// Bring the sensor back after repeated read failures.
+    fn reinit(&mut self) -> Result<(), SensorError> {
+        Ok(())
+    }
+
+    /// Switch gas measurement off, or back on if the sensor was set up with it.
+    fn set_gas_enabled(&mut self, _enabled: bool) -> Result<(), SensorError> {
+        Ok(())
+    }
+}
+
+/// A BME680 on an I2C bus, configured and ready for forced-mode readings.
+pub struct Sensor {
+    dev: Bme680<I2cdev, Delay>,
+    delay: Delay,
+    profile_dur: Duration,
+    measure_gas: bool,
+    device_path: String,
+    address: I2CAddress,
+    settings: Settings,
+    heater_profile: Option<HeaterProfile>,
+    /// Drift in °C past which the heater's ambient temperature is updated, when tracking it.
+    ambient_threshold_c: Option<f64>,
+    /// Second handle on the sensor to read the gas status with, when gas readings have to be
+    /// valid.
+    gas_status: Option<LinuxI2CDevice>,
+}
+
+impl Sensor {
+    /// Open the I2C bus, initialize the BME680 and apply `settings`.
+    ///
+    /// Used at startup and again to recover a sensor that dropped off the bus.
+    pub fn new(
+        device_path: &str,
+        address: I2CAddress,
+        settings: Settings,
+    ) -> Result<Sensor, SensorError> {
+        let mut delay = Delay {};
+        let i2c = I2cdev::new(device_path).map_err(|e| {
+            SensorError::Init(format!(
+                "failed to load I2C device {}: {:?}",
+                device_path, e
+            ))
+        })?;
+        let mut dev = Bme680::init(i2c, &mut delay, address)
+            .map_err(|e| SensorError::Init(format!("BME680 at {:?}: {:?}", address, e)))?;
+        dev.set_sensor_settings(&mut delay, settings)
+            .map_err(|e| SensorError::Init(format!("failed to apply settings: {:?}", e)))?;
+        let profile_dur = dev
+            .get_profile_dur(&settings.0)
+            .map_err(|e| SensorError::Init(format!("failed to get profile duration: {:?}", e)))?;
+        Ok(Sensor {
+            dev,
+            delay,
+            profile_dur,
+            measure_gas: settings.0.gas_sett.run_gas_measurement,
+            device_path: device_path.to_string(),
+            address,
+            settings,
+            heater_profile: None,
+            ambient_threshold_c: None,
+            gas_status: None,
+        })
+    }
+
+    /// Cycle the gas heater through `profile` across readings instead of keeping it at the
+    /// temperature in the settings.
+    pub fn with_heater_profile(mut self, profile: HeaterProfile) -> Self {
+        self.heater_profile = Some(profile);
+        self
+    }
+
+    /// Keep the ambient temperature the heater target is computed from at the measured
+    /// temperature, re-applying the settings whenever they drift apart by more than
+    /// `threshold_c`.
+    pub fn with_ambient_tracking(mut self, threshold_c: f64) -> Self {
+        self.ambient_threshold_c = Some(threshold_c);
+        self
+    }
+
+    /// Drop gas readings the sensor doesn't flag as valid with a stable heater.
+    pub fn with_gas_validation(mut self) -> Result<Self, SensorError> {
+        let device =
+            LinuxI2CDevice::new(&self.device_path, self.address.addr() as u16).map_err(|e| {
+                SensorError::Init(format!(
+                    "failed to open {} to read the gas status: {:?}",
+                    self.device_path, e
+                ))
+            })?;
+        self.gas_status = Some(device);
+        Ok(self)
+    }
+
+    /// Whether the gas resistance just measured can be used. Readings whose status can't be
+    /// read are kept rather than failing the whole reading.
+    fn gas_usable(&mut self) -> bool {
+        let device = match self.gas_status.as_mut() {
+            Some(device) => device,
+            None => return true,
+        };
+        match device.smbus_read_byte_data(GAS_R_LSB_REGISTER) {
+            Ok(value) => {
+                let status = GasStatus::from_register(value);
+                if !status.is_usable() {
+                    debug!("Skipping gas resistance, {:?}.", status);
+                }
+                status.is_usable()
+            }
+            Err(e) => {
+                warn!("Failed to read the gas status: {:?}", e);
+                true
+            }
+        }
+    }
+
+    /// Re-apply the settings with `measured_c` as the heater's ambient temperature when it has
+    /// drifted from the applied one. Failing to is only logged, the reading itself is fine.
+    fn track_ambient(&mut self, measured_c: f64) {
+        let threshold_c = match self.ambient_threshold_c {
+            Some(threshold_c) if self.measure_gas => threshold_c,
+            _ => return,
+        };
+        let current = self.settings.0.gas_sett.ambient_temperature;
+        if !ambient_needs_update(current, measured_c, threshold_c) {
+            return;
+        }
+        let ambient = measured_c.round().max(i8::MIN as f64).min(i8::MAX as f64) as i8;
+        let mut settings = self.settings;
+        settings.0.gas_sett.ambient_temperature = ambient;
+        match self.dev.set_sensor_settings(&mut self.delay, settings) {
+            Ok(()) => {
+                debug!(
+                    "Updated the gas heater ambient temperature from {} C to {} C.",
+                    current, ambient
+                );
+                self.settings = settings;
+            }
+            Err(e) => warn!(
+                "Failed to update the gas heater ambient temperature to {} C: {:?}",
+                ambient, e
+            ),
+        }
+    }
+
+    /// Trigger a forced-mode measurement and fetch its data, once.
+    fn measure(&mut self) -> Result<(FieldData, FieldDataCondition), (ErrorCategory, String)> {
+        self.dev
+            .set_sensor_mode(&mut self.delay, PowerMode::ForcedMode)
+            .map_err(|e| {
+                let (category, e) = categorize(e);
+                (category, format!("failed to set forced mode: {}", e))
+            })?;
+        self.dev
+            .get_sensor_data(&mut self.delay)
+            .map_err(categorize)
+    }
+
+    /// How long one forced-mode measurement takes with the applied settings.
+    pub fn profile_duration(&self) -> Duration {
+        self.profile_dur
+    }
+}
+
+impl EnvSensor for Sensor {
+    /// Trigger a forced-mode measurement and return the compensated values, timestamped when
+    /// the data was fetched.
+    fn read(&mut self) -> Result<Reading, SensorError> {
+        let heater_temp_c = match self.heater_profile.as_mut() {
+            Some(profile) if self.measure_gas => {
+                let temp = profile.advance();
+                let mut settings = self.settings;
+                settings.0.gas_sett.heatr_temp = Some(temp);
+                self.dev
+                    .set_sensor_settings(&mut self.delay, settings)
+                    .map_err(|e| {
+                        SensorError::Read(format!("failed to set heater to {} C: {:?}", temp, e))
+                    })?;
+                Some(temp)
+            }
+            _ => None,
+        };
+        let mut attempt = 1;
+        let (data, state) = loop {
+            match self.measure() {
+                Ok(measured) => break measured,
+                Err((ErrorCategory::Transient, e)) if attempt < BUS_ATTEMPTS => {
+                    debug!(
+                        "Transient bus error on attempt {}, retrying: {}",
+                        attempt, e
+                    );
+                    attempt += 1;
+                    thread::sleep(BUS_RETRY_DELAY);
+                }
+                Err((category, e)) if attempt > 1 => {
+                    return Err(SensorError::Read(format!(
+                        "{} error after {} attempts: {}",
+                        category.as_str(),
+                        attempt,
+                        e
+                    )))
+                }
+                Err((category, e)) => {
+                    return Err(SensorError::Read(format!(
+                        "{} error: {}",
+                        category.as_str(),
+                        e
+                    )))
+                }
+            }
+        };
+        if state != FieldDataCondition::NewData {
+            return Err(SensorError::NoNewData);
+        }
+        let gas_usable = self.measure_gas && self.gas_usable();
+        self.track_ambient(data.temperature_celsius() as f64);
+        Ok(Reading {
+            timestamp: unix_nanos(SystemTime::now()),
+            temperature_c: data.temperature_celsius() as f64,
+            humidity_percent: data.humidity_percent() as f64,
+            pressure_hpa: data.pressure_hpa() as f64,
+            gas_ohms: if gas_usable {
+                Some(data.gas_resistance_ohm() as f64)
+            } else {
+                None
+            },
+            heater_temp_c,
+        })
+    }
+
+    /// Reapply the settings with gas measurement switched, a no-op without gas configured.
+    fn set_gas_enabled(&mut self, enabled: bool) -> Result<(), SensorError> {
+        if !self.settings.0.gas_sett.run_gas_measurement || enabled == self.measure_gas {
+            return Ok(());
+        }
+        let mut settings = self.settings;
+        settings.0.gas_sett.run_gas_measurement = enabled;
+        self.dev
+            .set_sensor_settings(&mut self.delay, settings)
+            .map_err(|e| {
+                SensorError::Read(format!(
+                    "failed to turn gas measurement {}: {:?}",
+                    if enabled { "on" } else { "off" },
+                    e
+                ))
+            })?;
+        self.measure_gas = enabled;
+        Ok(())
+    }
+
+    /// Reopen the bus and initialize the sensor again with the same settings, carrying on with
+    /// the heater profile where it left off.
+    fn reinit(&mut self) -> Result<(), SensorError> {
+        let sensor = Sensor::new(&self.device_path, self.address, self.settings)?;
+        let heater_profile = self.heater_profile.take();
+        let ambient_threshold_c = self.ambient_threshold_c;
+        let gas_status = self.gas_status.take();
+        *self = sensor;
+        self.heater_profile = heater_profile;
+        self.ambient_threshold_c = ambient_threshold_c;
+        self.gas_status = gas_status;
+        Ok(())
+    }
+}
+
+/// Whether the heater's ambient temperature of `current_c` is more than `threshold_c` away from
+/// the `measured_c` temperature and should be updated.
+pub fn ambient_needs_update(current_c: i8, measured_c: f64, threshold_c: f64) -> bool {
+    (measured_c - current_c as f64).abs() > threshold_c
+}
+
+/// Read `sensor` `samples` times back to back and average the readings, reducing noise without
+/// the lag of smoothing across cycles. Reads without new data are skipped, any other failure
+/// fails the whole burst.
+///
+/// Reads through [`blocking_io`] and so panics outside a multi-threaded runtime.
+pub async fn read_averaged(
+    sensor: &mut dyn EnvSensor,
+    samples: usize,
+) -> Result<Reading, SensorError> {
+    let mut readings = Vec::with_capacity(samples);
+    for sample in 0..samples.max(1) {
+        if sample > 0 {
+            tokio::time::sleep(BURST_SETTLE).await;
+        }
+        match blocking_io(|| sensor.read()) {
+            Ok(reading) => readings.push(reading),
+            Err(SensorError::NoNewData) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    average_readings(&readings).ok_or(SensorError::NoNewData)
+}
+
+/// Heater temperatures cycled through in order, one per reading, starting over after the last.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaterProfile {
+    temps: Vec<u16>,
+    next: usize,
+}
+
+impl HeaterProfile {
+    /// `None` when `temps` is empty.
+    pub fn new(temps: Vec<u16>) -> Option<Self> {
+        if temps.is_empty() {
+            None
+        } else {
+            Some(HeaterProfile { temps, next: 0 })
+        }
+    }
+
+    /// The temperature for the next reading.
+    pub fn advance(&mut self) -> u16 {
+        let temp = self.temps[self.next];
+        self.next = (self.next + 1) % self.temps.len();
+        temp
+    }
+
+    pub fn temps(&self) -> &[u16] {
+        &self.temps
+    }
+}
+
+/// A sensor that plays back a scripted sequence of readings and errors, for exercising the read
+/// loop without hardware. Once the script runs out every read fails.
+#[derive(Debug, Default)]
+pub struct MockSensor {
+    script: VecDeque<Result<Reading, SensorError>>,
+    reinits: u32,
+    reinit_fails: bool,
+}
+
+impl MockSensor {
+    pub fn new(script: Vec<Result<Reading, SensorError>>) -> Self {
+        MockSensor {
+            script: script.into(),
+            reinits: 0,
+            reinit_fails: false,
+        }
+    }
+
+    /// Fail every re-init, like a sensor that is gone for good.
+    pub fn with_failing_reinit(mut self) -> Self {
+        self.reinit_fails = true;
+        self
+    }
+
+    /// How often the sensor has been re-initialized.
+    pub fn reinits(&self) -> u32 {
+        self.reinits
+    }
+}
+
+impl EnvSensor for MockSensor {
+    fn read(&mut self) -> Result<Reading, SensorError> {
+        self.script
+            .pop_front()
+            .unwrap_or_else(|| Err(SensorError::Read("mock script exhausted".into())))
+    }
+
+    fn reinit(&mut self) -> Result<(), SensorError> {
+        self.reinits += 1;
+        if self.reinit_fails {
+            return Err(SensorError::Init("mock re-init failed".into()));
+        }
+        Ok(())
+    }
+}
+
+/// Time between readings: the requested interval, clamped so it is never shorter than the
+/// sensor's profile duration, or three profile durations when nothing is requested. The flag
+/// is set when the requested interval had to be raised.
+pub fn read_interval(profile_dur: Duration, requested: Option<Duration>) -> (Duration, bool) {
+    match requested {
+        Some(requested) if requested < profile_dur => (profile_dur, true),
+        Some(requested) => (requested, false),
+        None => (profile_dur * 3, false),
+    }
+}
+
+/// Wait before the first reading. Zero skips it, anything else lasts at least one profile
+/// duration so the first reading is valid.
+pub fn stabilize_duration(requested: Duration, profile_dur: Duration) -> Duration {
+    if requested == Duration::from_secs(0) {
+        requested
+    } else {
+        requested.max(profile_dur)
+    }
+}
+
+/// Decides the gas sensor has warmed up once its resistance stays within `delta_pct` percent
+/// for `settle`.
+#[derive(Debug, Clone)]
+pub struct GasStability {
+    delta_pct: f64,
+    settle: Duration,
+    samples: VecDeque<(Instant, f64)>,
+}
+
+impl GasStability {
+    pub fn new(delta_pct: f64, settle: Duration) -> Self {
+        GasStability {
+            delta_pct,
+            settle,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Add a resistance measured at `now`, returning whether the resistances over the last
+    /// `settle` stayed within `delta_pct` percent of their minimum. Never true before there
+    /// are samples spanning `settle`.
+    pub fn push(&mut self, now: Instant, ohms: f64) -> bool {
+        self.samples.push_back((now, ohms));
+        // Keep the newest sample at or before the start of the span so it stays covered.
+        while self.samples.len() > 1
+            && now.saturating_duration_since(self.samples[1].0) >= self.settle
+        {
+            self.samples.pop_front();
+        }
+        if now.saturating_duration_since(self.samples[0].0) < self.settle {
+            return false;
+        }
+        let (min, max) = self.samples.iter().fold(
+            (f64::INFINITY, f64::NEG_INFINITY),
+            |(min, max), &(_, ohms)| (min.min(ohms), max.max(ohms)),
+        );
+        min > 0.0 && (max - min) / min * 100.0 < self.delta_pct
+    }
+}
+
+/// Counts consecutive read failures so transient errors don't end the process.
+#[derive(Debug, Clone)]
+pub struct FailureCounter {
+    consecutive: u32,
+    max: u32,
+}
+
+impl FailureCounter {
+    pub fn new(max: u32) -> Self {
+        FailureCounter {
+            consecutive: 0,
+            max,
+        }
+    }
+
+    /// Record a failure, returning true once `max` consecutive failures have been reached.
+    pub fn record_failure(&mut self) -> bool {
+        self.consecutive = self.consecutive.saturating_add(1);
+        self.consecutive >= self.max
+    }
+
+    pub fn record_success(&mut self) {
+        self.consecutive = 0;
+    }
+
+    pub fn consecutive(&self) -> u32 {
+        self.consecutive
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failures_count_up_to_the_limit() {
+        let mut failures = FailureCounter::new(3);
+        assert!(!failures.record_failure());
+        assert!(!failures.record_failure());
+        assert!(failures.record_failure());
+        assert_eq!(failures.consecutive(), 3);
+        assert!(failures.record_failure());
+    }
+
+    #[test]
+    fn success_resets_the_failure_count() {
+        let mut failures = FailureCounter::new(2);
+        failures.record_failure();
+        failures.record_success();
+        assert_eq!(failures.consecutive(), 0);
+        assert!(!failures.record_failure());
+        assert!(failures.record_failure());
+    }
+
+    #[test]
+    fn heater_profile_cycles_through_its_temperatures() {
+        let mut profile = HeaterProfile::new(vec![200, 250, 320]).unwrap();
+        let temps: Vec<u16> = (0..7).map(|_| profile.advance()).collect();
+        assert_eq!(temps, vec![200, 250, 320, 200, 250, 320, 200]);
+    }
+
+    #[test]
+    fn heater_profile_of_one_temperature_stays_put() {
+        let mut profile = HeaterProfile::new(vec![320]).unwrap();
+        assert_eq!((profile.advance(), profile.advance()), (320, 320));
+        assert_eq!(HeaterProfile::new(Vec::new()), None);
+    }
+
+    #[test]
+    fn ambient_is_updated_once_it_drifts_past_the_threshold() {
+        assert!(!ambient_needs_update(25, 25.0, 2.0));
+        assert!(!ambient_needs_update(25, 27.0, 2.0));
+        assert!(ambient_needs_update(25, 27.1, 2.0));
+        assert!(ambient_needs_update(25, 22.5, 2.0));
+        assert!(!ambient_needs_update(-5, -6.5, 2.0));
+    }
+
+    #[test]
+    fn gas_is_stable_once_it_plateaus_for_the_settle_time() {
+        let mut stability = GasStability::new(1.0, Duration::from_secs(60));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        // Warming up, the resistance climbs steeply.
+        for step in 0..10 {
+            assert!(!stability.push(at(step * 10), 10_000.0 * (step + 1) as f64));
+        }
+        let plateau = [100_200.0, 99_900.0, 100_100.0, 100_000.0];
+        for (step, &ohms) in plateau.iter().enumerate() {
+            assert!(!stability.push(at(100 + step as u64 * 10), ohms));
+        }
+        assert!(stability.push(at(150), 100_050.0));
+    }
+
+    #[test]
+    fn drifting_gas_never_stabilizes() {
+        let mut stability = GasStability::new(1.0, Duration::from_secs(60));
+        let start = Instant::now();
+        for step in 0..60 {
+            let ohms = 50_000.0 * (1.0 + 0.005 * step as f64);
+            assert!(!stability.push(start + Duration::from_secs(step * 10), ohms));
+        }
+    }
+
+    #[test]
+    fn busy_bus_errnos_are_transient() {
+        for &errno in &[EINTR, EAGAIN, EBUSY, ETIMEDOUT] {
+            assert_eq!(categorize_errno(errno), ErrorCategory::Transient);
+        }
+        // ENXIO when nothing answers at the address, EIO for a failed transfer.
+        assert_eq!(categorize_errno(6), ErrorCategory::Persistent);
+        assert_eq!(categorize_errno(5), ErrorCategory::Persistent);
+    }
+
+    #[test]
+    fn driver_errors_are_categorized_by_their_errno() {
+        let bus_error = |errno| LinuxI2CError::Io(io::Error::from_raw_os_error(errno));
+        let (category, message) = categorize(bme680::Error::I2CRead(bus_error(EAGAIN)));
+        assert_eq!(category, ErrorCategory::Transient);
+        assert!(message.starts_with("I2C error"), "{}", message);
+        let (category, _) = categorize(bme680::Error::I2CWrite(bus_error(EBUSY)));
+        assert_eq!(category, ErrorCategory::Transient);
+        let (category, _) = categorize(bme680::Error::I2CRead(bus_error(6)));
+        assert_eq!(category, ErrorCategory::Persistent);
+        let (category, _) = categorize(bme680::Error::DeviceNotFound);
+        assert_eq!(category, ErrorCategory::Persistent);
+    }
+
+    fn reading(timestamp: i64, temperature_c: f64, gas_ohms: f64) -> Reading {
+        Reading {
+            timestamp,
+            temperature_c,
+            humidity_percent: 40.0,
+            pressure_hpa: 1000.0,
+            gas_ohms: Some(gas_ohms),
+            heater_temp_c: None,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn burst_is_averaged_into_one_reading() {
+        let mut sensor = MockSensor::new(vec![
+            Ok(reading(1, 20.0, 40_000.0)),
+            Err(SensorError::NoNewData),
+            Ok(reading(3, 21.0, 50_000.0)),
+            Ok(reading(4, 22.5, 60_000.0)),
+        ]);
+        let averaged = read_averaged(&mut sensor, 4).await.unwrap();
+        assert_eq!(averaged.timestamp, 4);
+        assert!((averaged.temperature_c - 63.5 / 3.0).abs() < 1e-9);
+        assert_eq!(averaged.gas_ohms, Some(50_000.0));
+        assert_eq!(averaged.humidity_percent, 40.0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn burst_fails_on_the_first_read_error() {
+        let mut sensor = MockSensor::new(vec![
+            Ok(reading(1, 20.0, 40_000.0)),
+            Err(SensorError::Read("bus error".into())),
+            Ok(reading(3, 21.0, 50_000.0)),
+        ]);
+        assert!(matches!(
+            read_averaged(&mut sensor, 3).await,
+            Err(SensorError::Read(_))
+        ));
+        let mut sensor = MockSensor::new(vec![Err(SensorError::NoNewData)]);
+        assert!(matches!(
+            read_averaged(&mut sensor, 1).await,
+            Err(SensorError::NoNewData)
+        ));
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "needs a multi-threaded tokio runtime")]
+    async fn blocking_io_refuses_the_current_thread_runtime() {
+        blocking_io(|| ());
+    }
+
+    #[test]
+    #[should_panic(expected = "needs a multi-threaded tokio runtime")]
+    fn blocking_io_refuses_to_run_outside_a_runtime() {
+        blocking_io(|| ());
+    }
+
+    #[test]
+    fn gas_is_only_usable_when_valid_and_heat_stable() {
+        // The low bits of the register hold part of the gas resistance and range.
+        let status = GasStatus::from_register(0b1011_0101);
+        assert_eq!(
+            status,
+            GasStatus {
+                valid: true,
+                heat_stable: true
+            }
+        );
+        assert!(status.is_usable());
+        for &value in &[0b1001_0101u8, 0b1010_0101, 0b0000_1111] {
+            assert!(
+                !GasStatus::from_register(value).is_usable(),
+                "{:#010b}",
+                value
+            );
+        }
+        assert!(!GasStatus::from_register(0b0010_0000).heat_stable);
+        assert!(!GasStatus::from_register(0b0001_0000).valid);
+    }
+}
@@ -0,0 +1,205 @@
+//! Parsing BME680 tuning parameters from configuration strings.
+
+use bme680::{I2CAddress, IIRFilterSize, OversamplingSetting, PowerMode};
+
+/// Parse an oversampling rate such as `2x`, `16x` or `none`.
+pub fn parse_oversampling(value: &str) -> Result<OversamplingSetting, String> {
+    match value.trim().to_ascii_lowercase().trim_end_matches('x') {
+        "none" | "0" => Ok(OversamplingSetting::OSNone),
+        "1" => Ok(OversamplingSetting::OS1x),
+        "2" => Ok(OversamplingSetting::OS2x),
+        "4" => Ok(OversamplingSetting::OS4x),
+        "8" => Ok(OversamplingSetting::OS8x),
+        "16" => Ok(OversamplingSetting::OS16x),
+        _ => Err(format!(
+            "invalid oversampling {:?}, expected none, 1x, 2x, 4x, 8x or 16x",
+            value
+        )),
+    }
+}
+
+/// Parse an IIR filter coefficient such as `3` or `127`.
+pub fn parse_iir_filter(value: &str) -> Result<IIRFilterSize, String> {
+    match value.trim() {
+        "0" => Ok(IIRFilterSize::Size0),
+        "1" => Ok(IIRFilterSize::Size1),
+        "3" => Ok(IIRFilterSize::Size3),
+        "7" => Ok(IIRFilterSize::Size7),
+        "15" => Ok(IIRFilterSize::Size15),
+        "31" => Ok(IIRFilterSize::Size31),
+        "63" => Ok(IIRFilterSize::Size63),
+        "127" => Ok(IIRFilterSize::Size127),
+        _ => Err(format!(
+            "invalid IIR filter size {:?}, expected 0, 1, 3, 7, 15, 31, 63 or 127",
+            value
+        )),
+    }
+}
+
+/// Parse a gas heater profile such as `200,250,320`, in degrees Celsius. Empty entries are
+/// skipped, at least one temperature is required.
+pub fn parse_heater_profile(value: &str) -> Result<Vec<u16>, String> {
+    let temps = value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry
+                .parse::<u16>()
+                .map_err(|e| format!("invalid heater temperature {:?}: {}", entry, e))
+        })
+        .collect::<Result<Vec<u16>, String>>()?;
+    if temps.is_empty() {
+        return Err(format!("no heater temperatures in {:?}", value));
+    }
+    Ok(temps)
+}
+
+/// Parse the sensor's I2C address, `primary` (0x76) or `secondary` (0x77).
+pub fn parse_i2c_address(value: &str) -> Result<I2CAddress, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "primary" | "0x76" => Ok(I2CAddress::Primary),
+        "secondary" | "0x77" => Ok(I2CAddress::Secondary),
+        _ => Err(format!(
+            "invalid I2C address {:?}, expected primary, secondary, 0x76 or 0x77",
+            value
+        )),
+    }
+}
+
+/// One of several sensors on the bus and the label its readings are tagged with.
+#[derive(Debug, Clone)]
+pub struct SensorSpec {
+    pub address: I2CAddress,
+    pub label: String,
+}
+
+/// Parse a sensor list such as `0x76:indoor,0x77:outdoor`. Empty entries are skipped, labels
+/// must be unique.
+pub fn parse_sensors(value: &str) -> Result<Vec<SensorSpec>, String> {
+    let mut sensors: Vec<SensorSpec> = Vec::new();
+    for entry in value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        let (address, label) = entry
+            .split_once(':')
+            .ok_or_else(|| format!("invalid sensor {:?}, expected address:label", entry))?;
+        let label = label.trim();
+        if label.is_empty() {
+            return Err(format!("sensor {:?} has an empty label", entry));
+        }
+        if sensors.iter().any(|sensor| sensor.label == label) {
+            return Err(format!("sensor label {:?} is used twice", label));
+        }
+        sensors.push(SensorSpec {
+            address: parse_i2c_address(address)?,
+            label: label.to_string(),
+        });
+    }
+    Ok(sensors)
+}
+
+/// Bus the sensor is attached to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interface {
+    I2c,
+}
+
+/// Parse the interface the sensor is read over. Only `i2c` is available: the bme680 driver is
+/// built on the I2C traits alone, so `spi` is rejected with an explanation.
+pub fn parse_interface(value: &str) -> Result<Interface, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "i2c" => Ok(Interface::I2c),
+        "spi" => Err(format!(
+            "interface {:?} is not supported, the bme680 driver only talks to the sensor over I2C",
+            value
+        )),
+        _ => Err(format!("invalid interface {:?}, expected i2c", value)),
+    }
+}
+
+/// Parse the power mode readings are taken in. Only `forced` is available: the bme680 driver
+/// exposes sleep and forced mode, so continuous sampling is rejected with an explanation.
+pub fn parse_power_mode(value: &str) -> Result<PowerMode, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "forced" => Ok(PowerMode::ForcedMode),
+        "continuous" | "normal" | "parallel" => Err(format!(
+            "power mode {:?} is not supported, the bme680 driver only offers forced mode",
+            value
+        )),
+        _ => Err(format!("invalid power mode {:?}, expected forced", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oversampling_parses_rates() {
+        assert!(matches!(
+            parse_oversampling("2x"),
+            Ok(OversamplingSetting::OS2x)
+        ));
+        assert!(matches!(
+            parse_oversampling(" 4X "),
+            Ok(OversamplingSetting::OS4x)
+        ));
+        assert!(matches!(
+            parse_oversampling("8"),
+            Ok(OversamplingSetting::OS8x)
+        ));
+        assert!(matches!(
+            parse_oversampling("16x"),
+            Ok(OversamplingSetting::OS16x)
+        ));
+        assert!(matches!(
+            parse_oversampling("none"),
+            Ok(OversamplingSetting::OSNone)
+        ));
+    }
+
+    #[test]
+    fn oversampling_rejects_invalid_rates() {
+        for value in &["3x", "32x", "", "fast", "-2x"] {
+            assert!(parse_oversampling(value).is_err(), "{:?} parsed", value);
+        }
+    }
+
+    #[test]
+    fn iir_filter_parses_sizes() {
+        assert!(matches!(parse_iir_filter("0"), Ok(IIRFilterSize::Size0)));
+        assert!(matches!(parse_iir_filter(" 3 "), Ok(IIRFilterSize::Size3)));
+        assert!(matches!(
+            parse_iir_filter("127"),
+            Ok(IIRFilterSize::Size127)
+        ));
+    }
+
+    #[test]
+    fn iir_filter_rejects_invalid_sizes() {
+        for value in &["2", "128", "", "size3"] {
+            assert!(parse_iir_filter(value).is_err(), "{:?} parsed", value);
+        }
+    }
+
+    #[test]
+    fn sensors_parse_addresses_and_labels() {
+        let sensors = parse_sensors("0x76:indoor, secondary:outdoor ,").unwrap();
+        assert_eq!(sensors.len(), 2);
+        assert!(matches!(sensors[0].address, I2CAddress::Primary));
+        assert_eq!(sensors[0].label, "indoor");
+        assert!(matches!(sensors[1].address, I2CAddress::Secondary));
+        assert_eq!(sensors[1].label, "outdoor");
+        assert!(parse_sensors("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn sensors_reject_malformed_entries() {
+        for value in &["0x76", "0x76:", "0x78:indoor", "0x76:indoor,0x77:indoor"] {
+            assert!(parse_sensors(value).is_err(), "{:?} parsed", value);
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Destinations that readings are written to.
+
+mod batched;
+mod csv;
+mod dry_run;
+mod fan_out;
+mod http;
+mod influx;
+mod mqtt;
+mod stdout;
+
+pub use self::csv::CsvSink;
+pub use self::http::GenericHttp;
+pub use self::mqtt::MqttSink;
+pub use dry_run::DryRun;
+pub use fan_out::FanOut;
+pub use influx::{
+    parse_precision, truncate_timestamp, InfluxBackend, InfluxSink, Layout, Precision,
+};
+pub use stdout::StdoutJson;
+
+use crate::buffer::Batch;
+use crate::config::Config;
+use crate::mqtt::MqttPublisher;
+use crate::reading::Record;
+use async_trait::async_trait;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct SinkError(pub String);
+
+impl fmt::Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[async_trait]
+pub trait ReadingSink: Send {
+    /// Short name used in logs.
+    fn name(&self) -> &str;
+
+    async fn write_reading(&mut self, record: &Record) -> Result<(), SinkError>;
+
+    /// Write out anything held back, called once on shutdown.
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    /// Make sure the destination is reachable, called once at startup.
+    async fn check(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    /// Number of points waiting to be written.
+    fn buffered(&self) -> usize {
+        0
+    }
+}
+
+fn required(value: &Option<String>, name: &str) -> Result<String, SinkError> {
+    value
+        .clone()
+        .ok_or_else(|| SinkError(format!("Failed to load {}: not set", name)))
+}
+
+/// Build the sinks listed in `OUTPUT`, fanning out when there are several, or the dry-run sink
+/// when `DRY_RUN` is set. The `mqtt` output publishes through `mqtt`, sharing its connection.
+pub fn from_config(
+    config: &Config,
+    mqtt: Option<&MqttPublisher>,
+) -> Result<Box<dyn ReadingSink>, SinkError> {
+    let layout = if config.influx.single_measurement {
+        Layout::Single(config.influx.measurement.clone())
+    } else {
+        Layout::PerMeasurement
+    };
+    let precision = parse_precision(&config.influx.precision)
+        .map_err(|e| SinkError(format!("Failed to load INFLUX_PRECISION: {}", e)))?;
+    if config.dry_run {
+        return Ok(Box::new(DryRun { layout, precision }));
+    }
+    let mut sinks = config
+        .output
+        .split(',')
+        .map(str::trim)
+        .filter(|output| !output.is_empty())
+        .map(|output| build(output, config, &layout, precision, mqtt))
+        .collect::<Result<Vec<_>, SinkError>>()?;
+    match sinks.len() {
+        0 => Err(SinkError("Failed to load OUTPUT: not set".to_string())),
+        1 => Ok(sinks.remove(0)),
+        _ => Ok(Box::new(FanOut::new(sinks))),
+    }
+}
+
+fn build(
+    output: &str,
+    config: &Config,
+    layout: &Layout,
+    precision: Precision,
+    mqtt: Option<&MqttPublisher>,
+) -> Result<Box<dyn ReadingSink>, SinkError> {
+    match output {
+        "influxdb" => {
+            let influx = &config.influx;
+            let address = required(&influx.address, "INFLUX_ADDRESS")?;
+            let backend = match influx.version {
+                1 => InfluxBackend::V1 {
+                    http: reqwest::Client::new(),
+                    address,
+                    database: required(&influx.database, "INFLUX_DATABASE")?,
+                    retention_policy: influx.retention_policy.clone(),
+                    credentials: influx
+                        .user
+                        .clone()
+                        .map(|user| (user, influx.password.clone().unwrap_or_default())),
+                },
+                2 => {
+                    let token = required(&influx.token, "INFLUX_TOKEN")?;
+                    InfluxBackend::V2 {
+                        client: influxdb2_client::Client::new(address.clone(), token.clone()),
+                        http: reqwest::Client::new(),
+                        address,
+                        token,
+                        organization: required(&influx.organization, "INFLUX_ORGANIZATION")?,
+                        bucket: required(&influx.bucket, "INFLUX_BUCKET")?,
+                    }
+                }
+                other => {
+                    return Err(SinkError(format!(
+                        "Unknown INFLUX_VERSION {}, expected 1 or 2.",
+                        other
+                    )))
+                }
+            };
+            Ok(Box::new(
+                InfluxSink::new(
+                    backend,
+                    config.max_buffered_points,
+                    config.write_retry.policy(),
+                    Batch::new(
+                        config.batch_size,
+                        Duration::from_secs(config.batch_max_age_secs),
+                    ),
+                    layout.clone(),
+                )
+                .with_compression(influx.compress_writes)
+                .with_precision(precision)
+                .with_bucket_map(influx.bucket_map.clone())
+                .with_write_timeout(Duration::from_secs(config.write_timeout_secs)),
+            ))
+        }
+        "http" => Ok(Box::new(
+            GenericHttp::new(
+                required(&config.http.endpoint, "HTTP_ENDPOINT")?,
+                config.http.headers.clone(),
+                config.max_buffered_points,
+                config.write_retry.policy(),
+                Batch::new(
+                    config.batch_size,
+                    Duration::from_secs(config.batch_max_age_secs),
+                ),
+                layout.clone(),
+            )
+            .with_precision(precision)
+            .with_write_timeout(Duration::from_secs(config.write_timeout_secs)),
+        )),
+        "stdout" => Ok(Box::new(StdoutJson)),
+        "csv" => Ok(Box::new(CsvSink::new(
+            required(&config.csv.path, "CSV_PATH")?.into(),
+            config.csv.rotate_daily,
+        ))),
+        "mqtt" => match mqtt {
+            Some(publisher) => Ok(Box::new(MqttSink::new(publisher.clone()))),
+            None => Err(SinkError(
+                "Failed to load MQTT_BROKER: not set, but required by OUTPUT mqtt".to_string(),
+            )),
+        },
+        other => Err(SinkError(format!(
+            "Unknown OUTPUT {}, expected influxdb, http, stdout, csv or mqtt.",
+            other
+        ))),
+    }
+}
@@ -0,0 +1,276 @@
+use super::SinkError;
+use crate::buffer::{Batch, PointBuffer};
+use crate::retry::{retry, RetryPolicy};
+use influxdb2_client::models::DataPoint;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+
+const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Buffering, batching and retrying of point writes, for the sinks that send points over HTTP.
+///
+/// Points are accumulated until `batch` is due and then handed to a send function, once per
+/// bucket. Attempts are retried and time out after `write_timeout`; points that still fail to
+/// write stay buffered and are written ahead of the next batch.
+pub(super) struct PointWriter {
+    /// Where the points go, used in logs and errors.
+    destination: String,
+    /// Points with the bucket they go to, `None` for the configured one.
+    buffer: PointBuffer<(Option<String>, DataPoint)>,
+    retry: RetryPolicy,
+    batch: Batch,
+    batch_points: usize,
+    write_timeout: Duration,
+}
+
+impl PointWriter {
+    pub fn new(
+        destination: impl Into<String>,
+        max_buffered_points: usize,
+        retry: RetryPolicy,
+        batch: Batch,
+    ) -> Self {
+        PointWriter {
+            destination: destination.into(),
+            buffer: PointBuffer::new(max_buffered_points),
+            retry,
+            batch,
+            batch_points: 0,
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+        }
+    }
+
+    /// Give up on a write attempt that takes longer than `write_timeout`, counting it as failed.
+    pub fn with_write_timeout(mut self, write_timeout: Duration) -> Self {
+        self.write_timeout = write_timeout;
+        self
+    }
+
+    /// Buffer the points of one reading, then write the buffer with `send` if the batch is due.
+    pub async fn write<F, Fut>(
+        &mut self,
+        points: Vec<(Option<String>, DataPoint)>,
+        send: F,
+    ) -> Result<(), SinkError>
+    where
+        F: Fn(Vec<DataPoint>, Option<String>) -> Fut,
+        Fut: Future<Output = Result<(), String>>,
+    {
+        self.batch_points += points.len();
+        let dropped = self.buffer.extend(points);
+        if dropped > 0 {
+            warn!("Point buffer full, dropped {} oldest points.", dropped);
+        }
+        if !self.batch.add(Instant::now()) {
+            return Ok(());
+        }
+        let flushed = self.buffer.len().saturating_sub(self.batch_points);
+        self.write_buffer(send).await?;
+        if flushed > 0 {
+            info!("Flushed {} buffered points.", flushed);
+        }
+        Ok(())
+    }
+
+    /// Write whatever is buffered with `send`, due or not.
+    pub async fn flush<F, Fut>(&mut self, send: F) -> Result<(), SinkError>
+    where
+        F: Fn(Vec<DataPoint>, Option<String>) -> Fut,
+        Fut: Future<Output = Result<(), String>>,
+    {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.write_buffer(send).await
+    }
+
+    /// Number of points waiting to be written.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Write the buffer with one `send` per bucket. Points of buckets written before a failed
+    /// one aren't buffered again.
+    async fn write_buffer<F, Fut>(&mut self, send: F) -> Result<(), SinkError>
+    where
+        F: Fn(Vec<DataPoint>, Option<String>) -> Fut,
+        Fut: Future<Output = Result<(), String>>,
+    {
+        for (bucket, pending) in group_by_bucket(self.buffer.iter()) {
+            let destination = &self.destination;
+            let write_timeout = self.write_timeout;
+            let started = Instant::now();
+            retry(&self.retry, &format!("{} write", destination), || {
+                let write = send(pending.clone(), bucket.clone());
+                async move {
+                    match timeout(write_timeout, write).await {
+                        Ok(result) => result,
+                        Err(_) => {
+                            warn!("{} write timed out after {:?}.", destination, write_timeout);
+                            Err(format!("timed out after {:?}", write_timeout))
+                        }
+                    }
+                }
+            })
+            .await
+            .map_err(|e| {
+                SinkError(format!(
+                    "failed to write to {}, {} points buffered: {}",
+                    destination,
+                    self.buffer.len(),
+                    e
+                ))
+            })?;
+            debug!(
+                "Wrote {} points to {}{} in {:?}.",
+                pending.len(),
+                destination,
+                bucket
+                    .as_ref()
+                    .map(|bucket| format!(" bucket {}", bucket))
+                    .unwrap_or_default(),
+                started.elapsed()
+            );
+            self.buffer.retain(|(target, _)| *target != bucket);
+        }
+        self.batch.reset();
+        self.batch_points = 0;
+        Ok(())
+    }
+}
+
+/// Group points by the bucket they go to, keeping their order within each bucket.
+pub fn group_by_bucket<'a, T: Clone + 'a>(
+    points: impl IntoIterator<Item = &'a (Option<String>, T)>,
+) -> BTreeMap<Option<String>, Vec<T>> {
+    let mut groups: BTreeMap<Option<String>, Vec<T>> = BTreeMap::new();
+    for (bucket, point) in points {
+        groups
+            .entry(bucket.clone())
+            .or_default()
+            .push(point.clone());
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+
+    fn point(value: f64) -> DataPoint {
+        DataPoint::builder("temperature_c")
+            .field("value", value)
+            .build()
+            .unwrap()
+    }
+
+    fn writer(max_buffered_points: usize, batch_size: usize) -> PointWriter {
+        let retry = RetryPolicy {
+            retries: 0,
+            base_delay: Duration::from_millis(0),
+            max_delay: Duration::from_millis(0),
+        };
+        let batch = Batch::new(batch_size, Duration::from_secs(3600));
+        PointWriter::new("test", max_buffered_points, retry, batch)
+    }
+
+    #[tokio::test]
+    async fn buffered_points_are_written_first_once_writes_recover() {
+        let mut writer = writer(10, 1);
+        let online = AtomicBool::new(false);
+        let sent = Mutex::new(Vec::new());
+        let (online_ref, sent_ref) = (&online, &sent);
+        let send = move |points: Vec<DataPoint>, _: Option<String>| async move {
+            if !online_ref.load(Ordering::SeqCst) {
+                return Err("connection refused".to_string());
+            }
+            sent_ref.lock().unwrap().push(points);
+            Ok(())
+        };
+
+        assert!(writer.write(vec![(None, point(1.0))], &send).await.is_err());
+        assert!(writer.write(vec![(None, point(2.0))], &send).await.is_err());
+        assert_eq!(writer.buffered(), 2);
+
+        online.store(true, Ordering::SeqCst);
+        writer.write(vec![(None, point(3.0))], &send).await.unwrap();
+        assert_eq!(writer.buffered(), 0);
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec![vec![point(1.0), point(2.0), point(3.0)]]
+        );
+    }
+
+    #[tokio::test]
+    async fn slow_writes_time_out_and_stay_buffered() {
+        let mut writer = writer(10, 1).with_write_timeout(Duration::from_millis(10));
+        let send = |_: Vec<DataPoint>, _: Option<String>| async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        };
+
+        let started = Instant::now();
+        let error = writer
+            .write(vec![(None, point(1.0))], send)
+            .await
+            .unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(error.0.contains("timed out"), "{}", error);
+        assert_eq!(writer.buffered(), 1);
+    }
+
+    #[test]
+    fn points_are_grouped_by_bucket_in_order() {
+        let points = vec![
+            (None, 1),
+            (Some("derived".to_string()), 2),
+            (None, 3),
+            (Some("derived".to_string()), 4),
+            (Some("raw".to_string()), 5),
+        ];
+        let groups = group_by_bucket(&points);
+        assert_eq!(
+            groups.into_iter().collect::<Vec<_>>(),
+            vec![
+                (None, vec![1, 3]),
+                (Some("derived".to_string()), vec![2, 4]),
+                (Some("raw".to_string()), vec![5]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn one_write_is_sent_per_bucket() {
+        let mut writer = writer(10, 2);
+        let sent = Mutex::new(Vec::new());
+        let sent_ref = &sent;
+        let send = move |points: Vec<DataPoint>, bucket: Option<String>| async move {
+            sent_ref.lock().unwrap().push((bucket, points.len()));
+            Ok(())
+        };
+
+        writer
+            .write(
+                vec![(None, point(1.0)), (Some("derived".into()), point(2.0))],
+                &send,
+            )
+            .await
+            .unwrap();
+        writer
+            .write(
+                vec![(None, point(3.0)), (Some("derived".into()), point(4.0))],
+                &send,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec![(None, 2), (Some("derived".to_string()), 2)]
+        );
+    }
+}
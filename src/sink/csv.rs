@@ -0,0 +1,229 @@
+use super::{ReadingSink, SinkError};
+use crate::reading::Record;
+use async_trait::async_trait;
+use chrono::{SecondsFormat, TimeZone, Utc};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Appends one row per reading to a CSV file, optionally rotated daily.
+///
+/// The columns are `timestamp`, then the tag keys, then one column per measurement. They are
+/// fixed by the header of the file: an existing file keeps its header, a new one takes the
+/// columns of the first record written to it. Measurements without a column are skipped until
+/// the next rotation, missing ones are left empty.
+pub struct CsvSink {
+    path: PathBuf,
+    rotate_daily: bool,
+    current: Option<CsvFile>,
+}
+
+struct CsvFile {
+    path: PathBuf,
+    file: File,
+    header: Vec<String>,
+    warned_unknown: bool,
+}
+
+impl CsvSink {
+    pub fn new(path: PathBuf, rotate_daily: bool) -> Self {
+        CsvSink {
+            path,
+            rotate_daily,
+            current: None,
+        }
+    }
+
+    /// Path of the file a record belongs in, `name-YYYY-MM-DD.csv` when rotating.
+    fn path_for(&self, record: &Record) -> PathBuf {
+        if !self.rotate_daily {
+            return self.path.clone();
+        }
+        let date = Utc.timestamp_nanos(record.timestamp).format("%Y-%m-%d");
+        let stem = self
+            .path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "atmosphere".into());
+        let name = match self.path.extension() {
+            Some(ext) => format!("{}-{}.{}", stem, date, ext.to_string_lossy()),
+            None => format!("{}-{}", stem, date),
+        };
+        self.path.with_file_name(name)
+    }
+}
+
+impl CsvFile {
+    fn open(path: &Path, record: &Record) -> Result<Self, SinkError> {
+        let existing_header = match File::open(path) {
+            Ok(file) => {
+                let mut line = String::new();
+                BufReader::new(file)
+                    .read_line(&mut line)
+                    .map_err(|e| SinkError(format!("failed to read {}: {}", path.display(), e)))?;
+                let line = line.trim_end();
+                if line.is_empty() {
+                    None
+                } else {
+                    Some(line.split(',').map(|column| column.to_string()).collect())
+                }
+            }
+            Err(_) => None,
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| SinkError(format!("failed to open {}: {}", path.display(), e)))?;
+
+        let header = match existing_header {
+            Some(header) => header,
+            None => {
+                let header = header_for(record);
+                write_line(&mut file, &header.join(","))
+                    .map_err(|e| SinkError(format!("failed to write {}: {}", path.display(), e)))?;
+                header
+            }
+        };
+        info!("Appending readings to {}", path.display());
+        Ok(CsvFile {
+            path: path.to_path_buf(),
+            file,
+            header,
+            warned_unknown: false,
+        })
+    }
+
+    fn write_record(&mut self, record: &Record) -> Result<(), SinkError> {
+        let row: Vec<String> = self
+            .header
+            .iter()
+            .map(|column| {
+                if column == "timestamp" {
+                    return format_timestamp(record.timestamp);
+                }
+                if let Some(tag) = record.tags.get(column) {
+                    return escape(tag);
+                }
+                record
+                    .fields
+                    .iter()
+                    .find(|field| &field.measurement == column)
+                    .map(|field| field.value.to_string())
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        if !self.warned_unknown {
+            let header = &self.header;
+            let unknown: Vec<&str> = record
+                .fields
+                .iter()
+                .map(|field| field.measurement.as_str())
+                .filter(|measurement| !header.iter().any(|column| column == *measurement))
+                .collect();
+            if !unknown.is_empty() {
+                warn!(
+                    "{} has no columns for {}, skipping them until the next file.",
+                    self.path.display(),
+                    unknown.join(", ")
+                );
+                self.warned_unknown = true;
+            }
+        }
+
+        write_line(&mut self.file, &row.join(","))
+            .map_err(|e| SinkError(format!("failed to write {}: {}", self.path.display(), e)))
+    }
+}
+
+fn header_for(record: &Record) -> Vec<String> {
+    let mut header = vec!["timestamp".to_string()];
+    header.extend(record.tags.keys().cloned());
+    header.extend(record.fields.iter().map(|field| field.measurement.clone()));
+    header
+}
+
+/// ISO-8601 timestamp in UTC with nanosecond precision.
+pub fn format_timestamp(nanos: i64) -> String {
+    Utc.timestamp_nanos(nanos)
+        .to_rfc3339_opts(SecondsFormat::Nanos, true)
+}
+
+fn escape(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Write a whole row in one call and flush so a crash loses at most the current row.
+fn write_line(file: &mut File, line: &str) -> std::io::Result<()> {
+    file.write_all(format!("{}\n", line).as_bytes())?;
+    file.flush()
+}
+
+#[async_trait]
+impl ReadingSink for CsvSink {
+    fn name(&self) -> &str {
+        "csv"
+    }
+
+    async fn write_reading(&mut self, record: &Record) -> Result<(), SinkError> {
+        // Points of their own, like events and the location, don't fit the columns of the
+        // readings.
+        if record.measurement.is_some() {
+            return Ok(());
+        }
+        let path = self.path_for(record);
+        let reopen = match &self.current {
+            Some(current) => current.path != path,
+            None => true,
+        };
+        if reopen {
+            self.current = Some(CsvFile::open(&path, record)?);
+        }
+        match self.current.as_mut() {
+            Some(current) => current.write_record(record),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{event_record, EventKind};
+    use std::collections::BTreeMap;
+    use std::fs;
+
+    #[tokio::test]
+    async fn events_leave_the_columns_to_the_readings() {
+        let path =
+            std::env::temp_dir().join(format!("atmosphere-events-{}.csv", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut tags = BTreeMap::new();
+        tags.insert("host".to_string(), "pi".to_string());
+        let mut sink = CsvSink::new(path.clone(), false);
+
+        let event = event_record(EventKind::Stabilized, "stabilized", 0, &tags);
+        sink.write_reading(&event).await.unwrap();
+        let mut reading = Record::new(1_000_000_000, tags);
+        reading.push("temperature_c", 21.5);
+        reading.push("relative_humidity", 45.0);
+        sink.write_reading(&reading).await.unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            contents.lines().collect::<Vec<_>>(),
+            vec![
+                "timestamp,host,temperature_c,relative_humidity",
+                "1970-01-01T00:00:01.000000000Z,pi,21.5,45",
+            ]
+        );
+    }
+}
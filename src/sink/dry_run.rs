@@ -0,0 +1,29 @@
+use super::influx::{line_protocol, record_to_points, Layout, Precision};
+use super::{ReadingSink, SinkError};
+use crate::reading::Record;
+use async_trait::async_trait;
+use tracing::info;
+
+/// Logs the points a reading would have been written as, without writing anything.
+#[derive(Debug, Default)]
+pub struct DryRun {
+    pub layout: Layout,
+    pub precision: Precision,
+}
+
+#[async_trait]
+impl ReadingSink for DryRun {
+    fn name(&self) -> &str {
+        "dry-run"
+    }
+
+    async fn write_reading(&mut self, record: &Record) -> Result<(), SinkError> {
+        let points = record_to_points(record, &self.layout, self.precision)?;
+        let body = line_protocol(&points)
+            .map_err(|e| SinkError(format!("failed to serialize points: {}", e)))?;
+        for line in String::from_utf8_lossy(&body).lines() {
+            info!("Dry run, not writing: {}", line);
+        }
+        Ok(())
+    }
+}
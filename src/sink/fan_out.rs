@@ -0,0 +1,128 @@
+use super::{ReadingSink, SinkError};
+use crate::reading::Record;
+use async_trait::async_trait;
+use tracing::{debug, warn};
+
+/// Hands every reading to several sinks in turn. A failing sink doesn't keep the reading from
+/// the others; the failures are reported together once all have been tried.
+pub struct FanOut {
+    name: String,
+    sinks: Vec<Box<dyn ReadingSink>>,
+}
+
+impl FanOut {
+    pub fn new(sinks: Vec<Box<dyn ReadingSink>>) -> Self {
+        let names: Vec<&str> = sinks.iter().map(|sink| sink.name()).collect();
+        FanOut {
+            name: names.join(","),
+            sinks,
+        }
+    }
+}
+
+/// One error naming each sink that failed, or `Ok` when none did.
+fn collect_errors(failures: Vec<String>) -> Result<(), SinkError> {
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(SinkError(failures.join("; ")))
+    }
+}
+
+#[async_trait]
+impl ReadingSink for FanOut {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn write_reading(&mut self, record: &Record) -> Result<(), SinkError> {
+        let mut failures = Vec::new();
+        for sink in self.sinks.iter_mut() {
+            match sink.write_reading(record).await {
+                Ok(()) => debug!("Wrote reading to {}.", sink.name()),
+                Err(e) => {
+                    warn!("Failed to write reading to {}: {}", sink.name(), e);
+                    failures.push(format!("{}: {}", sink.name(), e));
+                }
+            }
+        }
+        collect_errors(failures)
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        let mut failures = Vec::new();
+        for sink in self.sinks.iter_mut() {
+            if let Err(e) = sink.flush().await {
+                failures.push(format!("{}: {}", sink.name(), e));
+            }
+        }
+        collect_errors(failures)
+    }
+
+    async fn check(&mut self) -> Result<(), SinkError> {
+        for sink in self.sinks.iter_mut() {
+            sink.check().await?;
+        }
+        Ok(())
+    }
+
+    fn buffered(&self) -> usize {
+        self.sinks.iter().map(|sink| sink.buffered()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+
+    /// Counts the records it is handed, failing every write when `fails` is set.
+    struct CountingSink {
+        name: &'static str,
+        fails: bool,
+        written: Arc<Mutex<usize>>,
+    }
+
+    #[async_trait]
+    impl ReadingSink for CountingSink {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn write_reading(&mut self, _: &Record) -> Result<(), SinkError> {
+            *self.written.lock().unwrap() += 1;
+            if self.fails {
+                Err(SinkError("broker unreachable".into()))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn failing_sink_does_not_keep_the_reading_from_the_others() {
+        let (failing, working) = (Arc::new(Mutex::new(0)), Arc::new(Mutex::new(0)));
+        let mut fan_out = FanOut::new(vec![
+            Box::new(CountingSink {
+                name: "mqtt",
+                fails: true,
+                written: failing.clone(),
+            }),
+            Box::new(CountingSink {
+                name: "stdout",
+                fails: false,
+                written: working.clone(),
+            }),
+        ]);
+        assert_eq!(fan_out.name(), "mqtt,stdout");
+
+        let error = fan_out
+            .write_reading(&Record::new(0, BTreeMap::new()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.0, "mqtt: broker unreachable");
+        assert_eq!(*failing.lock().unwrap(), 1);
+        assert_eq!(*working.lock().unwrap(), 1);
+    }
+}
@@ -0,0 +1,193 @@
+use super::batched::PointWriter;
+use super::influx::{record_to_points, send_line_protocol, Layout, Precision};
+use super::{ReadingSink, SinkError};
+use crate::buffer::Batch;
+use crate::reading::Record;
+use crate::retry::RetryPolicy;
+use async_trait::async_trait;
+use influxdb2_client::models::DataPoint;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::Duration;
+
+/// Posts readings as line protocol to any HTTP endpoint, such as an ingestion service that
+/// speaks line protocol without being InfluxDB.
+///
+/// Batches and buffers like [`super::InfluxSink`]: non-2xx responses count as failed writes,
+/// which are retried and then kept for the next batch.
+pub struct GenericHttp {
+    http: reqwest::Client,
+    endpoint: String,
+    /// Sent with every request, e.g. for auth.
+    headers: BTreeMap<String, String>,
+    writer: PointWriter,
+    layout: Layout,
+    precision: Precision,
+}
+
+impl GenericHttp {
+    pub fn new(
+        endpoint: String,
+        headers: BTreeMap<String, String>,
+        max_buffered_points: usize,
+        retry: RetryPolicy,
+        batch: Batch,
+        layout: Layout,
+    ) -> Self {
+        GenericHttp {
+            http: reqwest::Client::new(),
+            writer: PointWriter::new(endpoint.clone(), max_buffered_points, retry, batch),
+            endpoint,
+            headers,
+            layout,
+            precision: Precision::Nanoseconds,
+        }
+    }
+
+    /// Write timestamps in `precision` instead of nanoseconds.
+    pub fn with_precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Give up on a write attempt that takes longer than `write_timeout`, counting it as failed.
+    pub fn with_write_timeout(mut self, write_timeout: Duration) -> Self {
+        self.writer = self.writer.with_write_timeout(write_timeout);
+        self
+    }
+}
+
+/// Post `points` to `endpoint` with `headers`.
+fn post(
+    http: &reqwest::Client,
+    endpoint: &str,
+    headers: &BTreeMap<String, String>,
+    points: Vec<DataPoint>,
+) -> impl Future<Output = Result<(), String>> {
+    let mut request = http.post(endpoint);
+    for (name, value) in headers {
+        request = request.header(name.as_str(), value.as_str());
+    }
+    async move { send_line_protocol(request, &points, false).await }
+}
+
+#[async_trait]
+impl ReadingSink for GenericHttp {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    async fn write_reading(&mut self, record: &Record) -> Result<(), SinkError> {
+        let points = record_to_points(record, &self.layout, self.precision)?
+            .into_iter()
+            .map(|point| (None, point))
+            .collect();
+        let (http, endpoint, headers) = (&self.http, &self.endpoint, &self.headers);
+        self.writer
+            .write(points, |points, _| post(http, endpoint, headers, points))
+            .await
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        let (http, endpoint, headers) = (&self.http, &self.endpoint, &self.headers);
+        self.writer
+            .flush(|points, _| post(http, endpoint, headers, points))
+            .await
+    }
+
+    fn buffered(&self) -> usize {
+        self.writer.buffered()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server, StatusCode};
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+
+    /// Requests received by a mock server, as their `authorization` header and body.
+    type Received = Arc<Mutex<Vec<(Option<String>, String)>>>;
+
+    /// Answer every request with `status`, returning the server's URL and what it received.
+    fn serve(status: StatusCode) -> (String, Received) {
+        let received = Received::default();
+        let server_received = received.clone();
+        let make_svc = make_service_fn(move |_conn| {
+            let received = server_received.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let received = received.clone();
+                    async move {
+                        let auth = req
+                            .headers()
+                            .get("authorization")
+                            .map(|value| value.to_str().unwrap().to_string());
+                        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                        received
+                            .lock()
+                            .unwrap()
+                            .push((auth, String::from_utf8(body.to_vec()).unwrap()));
+                        Response::builder().status(status).body(Body::empty())
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let endpoint = format!("http://{}/ingest", server.local_addr());
+        tokio::spawn(server);
+        (endpoint, received)
+    }
+
+    fn sink(endpoint: String) -> GenericHttp {
+        let mut headers = BTreeMap::new();
+        headers.insert("Authorization".to_string(), "Bearer secret".to_string());
+        let retry = RetryPolicy {
+            retries: 0,
+            base_delay: Duration::from_millis(0),
+            max_delay: Duration::from_millis(0),
+        };
+        let batch = Batch::new(1, Duration::from_secs(3600));
+        GenericHttp::new(endpoint, headers, 10, retry, batch, Layout::PerMeasurement)
+    }
+
+    fn record() -> Record {
+        let mut tags = BTreeMap::new();
+        tags.insert("host".to_string(), "pi".to_string());
+        let mut record = Record::new(1_600_000_000_123_456_789, tags);
+        record.push("temperature_c", 21.5);
+        record.push("relative_humidity", 45.0);
+        record
+    }
+
+    #[tokio::test]
+    async fn points_are_posted_as_line_protocol_with_the_headers() {
+        let (endpoint, received) = serve(StatusCode::NO_CONTENT);
+        let mut sink = sink(endpoint);
+        sink.write_reading(&record()).await.unwrap();
+
+        assert_eq!(sink.buffered(), 0);
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].0.as_deref(), Some("Bearer secret"));
+        assert_eq!(
+            received[0].1.lines().collect::<Vec<_>>(),
+            vec![
+                "temperature_c,host=pi value=21.5 1600000000123456789",
+                "relative_humidity,host=pi value=45 1600000000123456789",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn non_2xx_responses_are_failed_writes() {
+        let (endpoint, received) = serve(StatusCode::INTERNAL_SERVER_ERROR);
+        let mut sink = sink(endpoint);
+        let error = sink.write_reading(&record()).await.unwrap_err();
+        assert!(error.0.contains("500"), "{}", error);
+        assert_eq!(sink.buffered(), 2);
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+}
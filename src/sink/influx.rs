@@ -0,0 +1,611 @@
+use super::batched::PointWriter;
+use super::{ReadingSink, SinkError};
+use crate::buffer::Batch;
+use crate::reading::Record;
+use crate::retry::RetryPolicy;
+use async_trait::async_trait;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::stream;
+use influxdb2_client::models::{DataPoint, WriteDataPoint};
+use influxdb2_client::Client;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::time::Duration;
+use tracing::debug;
+
+/// Where an [`InfluxSink`] sends its points.
+pub enum InfluxBackend {
+    /// InfluxDB 2.x, writing to an organization's bucket with a token.
+    V2 {
+        client: Client,
+        /// Used for the health check and compressed writes, which the client doesn't cover.
+        http: reqwest::Client,
+        address: String,
+        token: String,
+        organization: String,
+        bucket: String,
+    },
+    /// InfluxDB 1.x, posting line protocol to `/write` for a database and retention policy.
+    V1 {
+        http: reqwest::Client,
+        address: String,
+        database: String,
+        retention_policy: Option<String>,
+        credentials: Option<(String, String)>,
+    },
+}
+
+/// Unit of the timestamps in written points.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Precision {
+    Seconds,
+    Milliseconds,
+    Microseconds,
+    #[default]
+    Nanoseconds,
+}
+
+impl Precision {
+    /// The `precision` parameter of the 2.x write API.
+    fn v2_param(self) -> &'static str {
+        match self {
+            Precision::Seconds => "s",
+            Precision::Milliseconds => "ms",
+            Precision::Microseconds => "us",
+            Precision::Nanoseconds => "ns",
+        }
+    }
+
+    /// The `precision` parameter of the 1.x `/write` endpoint.
+    fn v1_param(self) -> &'static str {
+        match self {
+            Precision::Seconds => "s",
+            Precision::Milliseconds => "ms",
+            Precision::Microseconds => "u",
+            Precision::Nanoseconds => "n",
+        }
+    }
+
+    fn nanos_per_unit(self) -> i64 {
+        match self {
+            Precision::Seconds => 1_000_000_000,
+            Precision::Milliseconds => 1_000_000,
+            Precision::Microseconds => 1_000,
+            Precision::Nanoseconds => 1,
+        }
+    }
+}
+
+/// Parse a timestamp precision, `s`, `ms`, `us` or `ns`.
+pub fn parse_precision(value: &str) -> Result<Precision, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "s" => Ok(Precision::Seconds),
+        "ms" => Ok(Precision::Milliseconds),
+        "us" => Ok(Precision::Microseconds),
+        "ns" => Ok(Precision::Nanoseconds),
+        _ => Err(format!(
+            "invalid precision {:?}, expected s, ms, us or ns",
+            value
+        )),
+    }
+}
+
+/// `nanos` since the epoch in units of `precision`, rounded down, also before the epoch.
+pub fn truncate_timestamp(nanos: i64, precision: Precision) -> i64 {
+    nanos.div_euclid(precision.nanos_per_unit())
+}
+
+impl InfluxBackend {
+    /// Write `points` to `target`, the bucket on 2.x and the database on 1.x, or the configured
+    /// one when `None`, with timestamps in `precision`. The line protocol is gzipped when
+    /// `compress` is set; the 2.x client can neither compress nor take a precision, so those
+    /// 2.x writes are posted to `/api/v2/write` directly.
+    async fn write(
+        &self,
+        points: Vec<DataPoint>,
+        target: Option<&str>,
+        compress: bool,
+        precision: Precision,
+    ) -> Result<(), String> {
+        match self {
+            InfluxBackend::V2 {
+                client,
+                organization,
+                bucket,
+                ..
+            } if !compress && precision == Precision::Nanoseconds => client
+                .write(organization, target.unwrap_or(bucket), stream::iter(points))
+                .await
+                .map_err(|e| format!("{:?}", e)),
+            InfluxBackend::V2 {
+                http,
+                address,
+                token,
+                organization,
+                bucket,
+                ..
+            } => {
+                let request = http
+                    .post(format!("{}/api/v2/write", address.trim_end_matches('/')))
+                    .query(&[
+                        ("org", organization.as_str()),
+                        ("bucket", target.unwrap_or(bucket)),
+                        ("precision", precision.v2_param()),
+                    ])
+                    .header("Authorization", format!("Token {}", token));
+                send_line_protocol(request, &points, compress).await
+            }
+            InfluxBackend::V1 {
+                http,
+                address,
+                database,
+                retention_policy,
+                credentials,
+            } => {
+                let mut query = vec![
+                    ("db", target.unwrap_or(database)),
+                    ("precision", precision.v1_param()),
+                ];
+                if let Some(rp) = retention_policy {
+                    query.push(("rp", rp.as_str()));
+                }
+                let mut request = http
+                    .post(format!("{}/write", address.trim_end_matches('/')))
+                    .query(&query);
+                if let Some((user, password)) = credentials {
+                    request = request.basic_auth(user, Some(password));
+                }
+                send_line_protocol(request, &points, compress).await
+            }
+        }
+    }
+
+    /// Ask the server whether it is up: `/health` on 2.x, which must report `pass`, and `/ping`
+    /// on 1.x.
+    pub async fn health(&self) -> Result<(), String> {
+        let (http, url) = match self {
+            InfluxBackend::V2 { http, address, .. } => {
+                (http, format!("{}/health", address.trim_end_matches('/')))
+            }
+            InfluxBackend::V1 { http, address, .. } => {
+                (http, format!("{}/ping", address.trim_end_matches('/')))
+            }
+        };
+        let response = http
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| format!("{} is unreachable: {}", url, e))?;
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            return Err(format!("{} returned {} {}", url, status, text.trim()));
+        }
+        if let InfluxBackend::V2 { .. } = self {
+            let health: serde_json::Value = serde_json::from_str(&text)
+                .map_err(|e| format!("{} returned invalid JSON: {}", url, e))?;
+            if health["status"] != "pass" {
+                return Err(format!("{} reports {}", url, health));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Post `points` as the line protocol body of `request`, optionally gzipped. Responses other
+/// than 2xx are errors.
+pub(super) async fn send_line_protocol(
+    mut request: reqwest::RequestBuilder,
+    points: &[DataPoint],
+    compress: bool,
+) -> Result<(), String> {
+    let mut body = line_protocol(points).map_err(|e| e.to_string())?;
+    if compress {
+        let compressed = gzip(&body).map_err(|e| format!("failed to compress: {}", e))?;
+        debug!(
+            "Compressed {} bytes of line protocol to {} ({:.0}%).",
+            body.len(),
+            compressed.len(),
+            compressed.len() as f64 * 100.0 / body.len().max(1) as f64
+        );
+        body = compressed;
+        request = request.header("Content-Encoding", "gzip");
+    }
+    let response = request.body(body).send().await.map_err(|e| e.to_string())?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        Err(format!("{} {}", status, text.trim()))
+    }
+}
+
+/// Gzip a request body.
+pub fn gzip(body: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body)?;
+    encoder.finish()
+}
+
+/// Serialize points as newline-terminated line protocol.
+pub fn line_protocol(points: &[DataPoint]) -> std::io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    for point in points {
+        point.write_data_point_to(&mut body)?;
+        if body.last() != Some(&b'\n') {
+            body.push(b'\n');
+        }
+    }
+    Ok(body)
+}
+
+/// How the fields of a record are laid out as points.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Layout {
+    /// One measurement per field, each with a single `value` field.
+    #[default]
+    PerMeasurement,
+    /// One point in the named measurement, with a field per record field.
+    Single(String),
+}
+
+/// Writes readings to InfluxDB in the configured [`Layout`].
+///
+/// Readings are accumulated until `batch` is due and then sent in a single write. Points that
+/// fail to write after retrying stay buffered and are written ahead of the next batch.
+pub struct InfluxSink {
+    backend: InfluxBackend,
+    writer: PointWriter,
+    bucket_map: BTreeMap<String, String>,
+    layout: Layout,
+    compress: bool,
+    precision: Precision,
+}
+
+impl InfluxSink {
+    pub fn new(
+        backend: InfluxBackend,
+        max_buffered_points: usize,
+        retry: RetryPolicy,
+        batch: Batch,
+        layout: Layout,
+    ) -> Self {
+        InfluxSink {
+            backend,
+            writer: PointWriter::new("influxdb", max_buffered_points, retry, batch),
+            bucket_map: BTreeMap::new(),
+            layout,
+            compress: false,
+            precision: Precision::Nanoseconds,
+        }
+    }
+
+    /// Give up on a write attempt that takes longer than `write_timeout`, counting it as failed.
+    pub fn with_write_timeout(mut self, write_timeout: Duration) -> Self {
+        self.writer = self.writer.with_write_timeout(write_timeout);
+        self
+    }
+
+    /// Write the points of the measurements in `bucket_map` to the bucket they map to, or the
+    /// database on 1.x, instead of the configured one.
+    pub fn with_bucket_map(mut self, bucket_map: BTreeMap<String, String>) -> Self {
+        self.bucket_map = bucket_map;
+        self
+    }
+
+    /// Write timestamps in `precision` instead of nanoseconds.
+    pub fn with_precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Gzip the request bodies, trading a little CPU for less traffic.
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+}
+
+/// Measurement names of the points [`record_to_points`] builds for `record`, in the same order.
+fn point_measurements<'a>(record: &'a Record, layout: &'a Layout) -> Vec<&'a str> {
+    match (&record.measurement, layout) {
+        (None, Layout::PerMeasurement) => record
+            .fields
+            .iter()
+            .map(|field| field.measurement.as_str())
+            .collect(),
+        (Some(measurement), _) | (None, Layout::Single(measurement)) => {
+            vec![measurement.as_str()]
+        }
+    }
+}
+
+/// Build the points for a record, sharing its tags and timestamp. Field tags go on that field's
+/// point, or on the single point when all fields share one, replacing record tags of the same
+/// key. Records with a measurement of their own are always written as a single point.
+/// Timestamps are in `precision`.
+pub fn record_to_points(
+    record: &Record,
+    layout: &Layout,
+    precision: Precision,
+) -> Result<Vec<DataPoint>, SinkError> {
+    let timestamp = truncate_timestamp(record.timestamp, precision);
+    let builder = |measurement: &str, field_tags: &[&BTreeMap<String, String>]| {
+        let mut builder = DataPoint::builder(measurement);
+        let mut tags = record.tags.clone();
+        for extra in field_tags {
+            tags.extend(
+                extra
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.clone())),
+            );
+        }
+        for (key, value) in &tags {
+            builder = builder.tag(key.as_str(), value.as_str());
+        }
+        for (key, value) in &record.text {
+            builder = builder.field(key.as_str(), value.as_str());
+        }
+        builder.timestamp(timestamp)
+    };
+    let point_error = |measurement: &str, e| {
+        SinkError(format!(
+            "failed to create data point {}: {:?}",
+            measurement, e
+        ))
+    };
+    match (&record.measurement, layout) {
+        (None, Layout::PerMeasurement) => record
+            .fields
+            .iter()
+            .map(|field| {
+                builder(&field.measurement, &[&field.tags])
+                    .field("value", field.value)
+                    .build()
+                    .map_err(|e| point_error(&field.measurement, e))
+            })
+            .collect(),
+        (Some(measurement), _) | (None, Layout::Single(measurement)) => {
+            let field_tags: Vec<_> = record.fields.iter().map(|field| &field.tags).collect();
+            let mut point = builder(measurement, &field_tags);
+            for field in &record.fields {
+                point = point.field(field.measurement.as_str(), field.value);
+            }
+            point
+                .build()
+                .map(|point| vec![point])
+                .map_err(|e| point_error(measurement, e))
+        }
+    }
+}
+
+#[async_trait]
+impl ReadingSink for InfluxSink {
+    fn name(&self) -> &str {
+        "influxdb"
+    }
+
+    async fn write_reading(&mut self, record: &Record) -> Result<(), SinkError> {
+        let points = record_to_points(record, &self.layout, self.precision)?;
+        let bucket_map = &self.bucket_map;
+        let routed = point_measurements(record, &self.layout)
+            .into_iter()
+            .zip(points)
+            .map(|(measurement, point)| (bucket_map.get(measurement).cloned(), point))
+            .collect();
+        let backend = &self.backend;
+        let (compress, precision) = (self.compress, self.precision);
+        self.writer
+            .write(routed, |points, bucket| async move {
+                backend
+                    .write(points, bucket.as_deref(), compress, precision)
+                    .await
+            })
+            .await
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        let backend = &self.backend;
+        let (compress, precision) = (self.compress, self.precision);
+        self.writer
+            .flush(|points, bucket| async move {
+                backend
+                    .write(points, bucket.as_deref(), compress, precision)
+                    .await
+            })
+            .await
+    }
+
+    async fn check(&mut self) -> Result<(), SinkError> {
+        self.backend
+            .health()
+            .await
+            .map_err(|e| SinkError(format!("InfluxDB health check failed: {}", e)))
+    }
+
+    fn buffered(&self) -> usize {
+        self.writer.buffered()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Response, Server, StatusCode};
+    use std::convert::Infallible;
+
+    fn record() -> Record {
+        let mut tags = BTreeMap::new();
+        tags.insert("host".to_string(), "pi".to_string());
+        let mut record = Record::new(1_600_000_000_123_456_789, tags);
+        record.push("temperature_c", 21.5);
+        record.push("relative_humidity", 45.0);
+        record.push("pressure_hpa", 1013.0);
+        record.push("gas_resistance_ohms", 50_000.0);
+        record
+    }
+
+    fn lines(points: &[DataPoint]) -> Vec<String> {
+        String::from_utf8(line_protocol(points).unwrap())
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn points_of_a_record_share_its_timestamp() {
+        let points =
+            record_to_points(&record(), &Layout::PerMeasurement, Precision::Nanoseconds).unwrap();
+        assert_eq!(
+            lines(&points),
+            vec![
+                "temperature_c,host=pi value=21.5 1600000000123456789",
+                "relative_humidity,host=pi value=45 1600000000123456789",
+                "pressure_hpa,host=pi value=1013 1600000000123456789",
+                "gas_resistance_ohms,host=pi value=50000 1600000000123456789",
+            ]
+        );
+    }
+
+    #[test]
+    fn single_layout_writes_every_field_on_one_point() {
+        let layout = Layout::Single("environment".into());
+        let points = record_to_points(&record(), &layout, Precision::Nanoseconds).unwrap();
+        assert_eq!(
+            lines(&points),
+            vec![
+                "environment,host=pi gas_resistance_ohms=50000,pressure_hpa=1013,\
+                 relative_humidity=45,temperature_c=21.5 1600000000123456789"
+            ]
+        );
+    }
+
+    /// Serve `body` with `status` to every request, returning the server's base URL.
+    fn serve(status: StatusCode, body: &'static str) -> String {
+        let make_svc = make_service_fn(move |_conn| async move {
+            Ok::<_, Infallible>(service_fn(move |_req| async move {
+                Response::builder().status(status).body(Body::from(body))
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let address = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        address
+    }
+
+    fn v2(address: String) -> InfluxBackend {
+        InfluxBackend::V2 {
+            client: Client::new(address.clone(), "token"),
+            http: reqwest::Client::new(),
+            address,
+            token: "token".into(),
+            organization: "home".into(),
+            bucket: "atmosphere".into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn health_check_passes_for_a_healthy_server() {
+        let address = serve(StatusCode::OK, r#"{"name":"influxdb","status":"pass"}"#);
+        assert_eq!(v2(address).health().await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn health_check_fails_for_an_unhealthy_server() {
+        let address = serve(StatusCode::OK, r#"{"name":"influxdb","status":"fail"}"#);
+        assert!(v2(address).health().await.unwrap_err().contains("fail"));
+        let address = serve(StatusCode::SERVICE_UNAVAILABLE, "starting");
+        assert!(v2(address).health().await.unwrap_err().contains("503"));
+        let address = serve(StatusCode::OK, "not json");
+        assert!(v2(address).health().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn health_check_fails_for_an_unreachable_server() {
+        let error = v2("http://127.0.0.1:1".into()).health().await.unwrap_err();
+        assert!(error.contains("unreachable"), "{}", error);
+    }
+
+    #[test]
+    fn field_tags_merge_with_and_override_record_tags() {
+        let mut record = record();
+        record.tags.insert("location".into(), "office".into());
+        record.fields.truncate(1);
+        let mut field_tags = BTreeMap::new();
+        field_tags.insert("sensor_type".to_string(), "voc".to_string());
+        field_tags.insert("location".to_string(), "bench".to_string());
+        record.push_tagged("gas_resistance_ohms", 50_000.0, field_tags);
+
+        let points =
+            record_to_points(&record, &Layout::PerMeasurement, Precision::Nanoseconds).unwrap();
+        assert_eq!(
+            lines(&points),
+            vec![
+                "temperature_c,host=pi,location=office value=21.5 1600000000123456789",
+                "gas_resistance_ohms,host=pi,location=bench,sensor_type=voc value=50000 \
+                 1600000000123456789",
+            ]
+        );
+    }
+
+    #[test]
+    fn gzipped_line_protocol_decompresses_to_the_original() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let points: Vec<DataPoint> = (0..50)
+            .flat_map(|_| {
+                record_to_points(&record(), &Layout::PerMeasurement, Precision::Nanoseconds)
+                    .unwrap()
+            })
+            .collect();
+        let body = line_protocol(&points).unwrap();
+        let compressed = gzip(&body).unwrap();
+        assert!(compressed.len() < body.len() / 4);
+
+        let mut decompressed = Vec::new();
+        GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, body);
+    }
+
+    #[test]
+    fn timestamps_are_truncated_to_the_precision() {
+        let nanos = 1_600_000_000_123_456_789;
+        assert_eq!(truncate_timestamp(nanos, Precision::Seconds), 1_600_000_000);
+        assert_eq!(
+            truncate_timestamp(nanos, Precision::Milliseconds),
+            1_600_000_000_123
+        );
+        assert_eq!(
+            truncate_timestamp(nanos, Precision::Microseconds),
+            1_600_000_000_123_456
+        );
+        assert_eq!(truncate_timestamp(nanos, Precision::Nanoseconds), nanos);
+        // Rounded down, not towards zero, before the epoch.
+        assert_eq!(truncate_timestamp(-1, Precision::Seconds), -1);
+        assert_eq!(truncate_timestamp(-1_000_000_000, Precision::Seconds), -1);
+    }
+
+    #[test]
+    fn precisions_parse_and_apply_to_every_point() {
+        assert_eq!(parse_precision(" S "), Ok(Precision::Seconds));
+        assert_eq!(parse_precision("us"), Ok(Precision::Microseconds));
+        assert_eq!(
+            parse_precision(""),
+            Err("invalid precision \"\", expected s, ms, us or ns".to_string())
+        );
+        assert!(parse_precision("ps").is_err());
+
+        let points =
+            record_to_points(&record(), &Layout::PerMeasurement, Precision::Seconds).unwrap();
+        assert!(lines(&points)
+            .iter()
+            .all(|line| line.ends_with(" 1600000000")));
+    }
+}
@@ -0,0 +1,29 @@
+use super::stdout::record_to_json;
+use super::{ReadingSink, SinkError};
+use crate::mqtt::MqttPublisher;
+use crate::reading::Record;
+use async_trait::async_trait;
+
+/// Publishes every record, derived values included, as JSON to `<prefix>/<host>/record`.
+pub struct MqttSink {
+    publisher: MqttPublisher,
+}
+
+impl MqttSink {
+    pub fn new(publisher: MqttPublisher) -> Self {
+        MqttSink { publisher }
+    }
+}
+
+#[async_trait]
+impl ReadingSink for MqttSink {
+    fn name(&self) -> &str {
+        "mqtt"
+    }
+
+    async fn write_reading(&mut self, record: &Record) -> Result<(), SinkError> {
+        self.publisher
+            .publish_json(&self.publisher.record_topic(), &record_to_json(record))
+            .map_err(|e| SinkError(format!("failed to publish to MQTT: {:?}", e)))
+    }
+}
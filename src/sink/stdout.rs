@@ -0,0 +1,44 @@
+use super::{ReadingSink, SinkError};
+use crate::reading::Record;
+use async_trait::async_trait;
+use serde_json::{Map, Value};
+use std::io::Write;
+
+/// Writes one JSON object per reading to stdout.
+#[derive(Debug, Default)]
+pub struct StdoutJson;
+
+/// Flatten a record into `{"timestamp": .., <tags>.., <measurement>: value..}`, with field tags
+/// next to their field.
+pub fn record_to_json(record: &Record) -> Value {
+    let mut object = Map::new();
+    object.insert("timestamp".into(), Value::from(record.timestamp));
+    for (key, value) in &record.tags {
+        object.insert(key.clone(), Value::from(value.as_str()));
+    }
+    for field in &record.fields {
+        object.insert(field.measurement.clone(), Value::from(field.value));
+        for (key, value) in &field.tags {
+            object.insert(key.clone(), Value::from(value.as_str()));
+        }
+    }
+    for (key, value) in &record.text {
+        object.insert(key.clone(), Value::from(value.as_str()));
+    }
+    Value::Object(object)
+}
+
+#[async_trait]
+impl ReadingSink for StdoutJson {
+    fn name(&self) -> &str {
+        "stdout"
+    }
+
+    async fn write_reading(&mut self, record: &Record) -> Result<(), SinkError> {
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+        writeln!(stdout, "{}", record_to_json(record))
+            .and_then(|_| stdout.flush())
+            .map_err(|e| SinkError(format!("failed to write to stdout: {}", e)))
+    }
+}
@@ -0,0 +1,323 @@
+//! Smoothing filters applied to readings before they are written.
+
+use crate::reading::Reading;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Simple moving average over the last `window` values.
+///
+/// Until the window fills up, the average of the values seen so far is returned, so no readings
+/// are dropped at startup.
+#[derive(Debug, Clone)]
+pub struct MovingAverage {
+    window: usize,
+    values: VecDeque<f64>,
+    sum: f64,
+}
+
+impl MovingAverage {
+    pub fn new(window: usize) -> Self {
+        let window = window.max(1);
+        MovingAverage {
+            window,
+            values: VecDeque::with_capacity(window),
+            sum: 0.0,
+        }
+    }
+
+    /// Add a value and return the average of the current window.
+    pub fn push(&mut self, value: f64) -> f64 {
+        if self.values.len() == self.window {
+            if let Some(oldest) = self.values.pop_front() {
+                self.sum -= oldest;
+            }
+        }
+        self.values.push_back(value);
+        self.sum += value;
+        self.sum / self.values.len() as f64
+    }
+}
+
+/// Exponential moving average, weighting each new value by `alpha` and the previous average by
+/// `1 - alpha`. It lags less than a moving average of similar smoothness.
+///
+/// The first value initializes the average and is returned as is.
+#[derive(Debug, Clone)]
+pub struct EmaFilter {
+    alpha: f64,
+    value: Option<f64>,
+}
+
+impl EmaFilter {
+    /// `alpha` is clamped to `0..=1`, where 1 passes values through unchanged.
+    pub fn new(alpha: f64) -> Self {
+        EmaFilter {
+            alpha: alpha.clamp(0.0, 1.0),
+            value: None,
+        }
+    }
+
+    /// Add a value and return the updated average.
+    pub fn push(&mut self, value: f64) -> f64 {
+        let average = match self.value {
+            Some(previous) => previous + self.alpha * (value - previous),
+            None => value,
+        };
+        self.value = Some(average);
+        average
+    }
+}
+
+/// Mean of the values from the trailing `window`, by timestamp, so it follows irregular read
+/// intervals. The current value is always part of the mean.
+#[derive(Debug, Clone)]
+pub struct TimeWindowMean {
+    /// Window length in nanoseconds.
+    window: i64,
+    /// Values with their timestamps, oldest first.
+    values: VecDeque<(i64, f64)>,
+    sum: f64,
+}
+
+impl TimeWindowMean {
+    pub fn new(window: Duration) -> Self {
+        TimeWindowMean {
+            window: window.as_nanos() as i64,
+            values: VecDeque::new(),
+            sum: 0.0,
+        }
+    }
+
+    /// Add a value taken at `timestamp`, in nanoseconds since the epoch, and return the mean of
+    /// the values from the window ending at it. A timestamp before the last one starts over,
+    /// since the clock was set back.
+    pub fn push(&mut self, timestamp: i64, value: f64) -> f64 {
+        if self
+            .values
+            .back()
+            .is_some_and(|&(last, _)| timestamp < last)
+        {
+            self.reset();
+        }
+        while let Some(&(taken, oldest)) = self.values.front() {
+            if timestamp - taken < self.window {
+                break;
+            }
+            self.values.pop_front();
+            self.sum -= oldest;
+        }
+        self.values.push_back((timestamp, value));
+        self.sum += value;
+        self.sum / self.values.len() as f64
+    }
+
+    pub fn reset(&mut self) {
+        self.values.clear();
+        self.sum = 0.0;
+    }
+}
+
+/// Which filter readings are smoothed with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Smoothing {
+    /// Simple moving average over this many values.
+    MovingAverage(usize),
+    /// Exponential moving average with this alpha.
+    Exponential(f64),
+    /// Mean over this much time.
+    TimeWindow(Duration),
+}
+
+/// Parse the smoothing type, `sma`, `ema` or `window`, with the window used by `sma`, the alpha
+/// used by `ema` and the seconds used by `window`. `None` when smoothing is off, i.e. `sma`
+/// with a window of 0.
+pub fn parse_smoothing(
+    kind: &str,
+    window: usize,
+    alpha: f64,
+    window_secs: u64,
+) -> Result<Option<Smoothing>, String> {
+    match kind.trim().to_ascii_lowercase().as_str() {
+        "sma" if window == 0 => Ok(None),
+        "sma" => Ok(Some(Smoothing::MovingAverage(window))),
+        "ema" if alpha > 0.0 && alpha <= 1.0 => Ok(Some(Smoothing::Exponential(alpha))),
+        "ema" => Err(format!("invalid alpha {}, expected 0 < alpha <= 1", alpha)),
+        "window" if window_secs > 0 => Ok(Some(Smoothing::TimeWindow(Duration::from_secs(
+            window_secs,
+        )))),
+        "window" => Err("window smoothing needs WINDOW_SECS above 0".to_string()),
+        _ => Err(format!(
+            "invalid smoothing type {:?}, expected sma, ema or window",
+            kind
+        )),
+    }
+}
+
+/// A smoothing filter of either kind.
+#[derive(Debug, Clone)]
+pub enum Filter {
+    MovingAverage(MovingAverage),
+    Exponential(EmaFilter),
+    TimeWindow(TimeWindowMean),
+}
+
+impl Filter {
+    pub fn new(smoothing: Smoothing) -> Self {
+        match smoothing {
+            Smoothing::MovingAverage(window) => Filter::MovingAverage(MovingAverage::new(window)),
+            Smoothing::Exponential(alpha) => Filter::Exponential(EmaFilter::new(alpha)),
+            Smoothing::TimeWindow(window) => Filter::TimeWindow(TimeWindowMean::new(window)),
+        }
+    }
+
+    /// Add a value taken at `timestamp` and return the smoothed one. Only the time window goes by
+    /// the timestamp.
+    pub fn push(&mut self, timestamp: i64, value: f64) -> f64 {
+        match self {
+            Filter::MovingAverage(filter) => filter.push(value),
+            Filter::Exponential(filter) => filter.push(value),
+            Filter::TimeWindow(filter) => filter.push(timestamp, value),
+        }
+    }
+}
+
+/// One filter per raw sensor field.
+#[derive(Debug, Clone)]
+pub struct ReadingSmoother {
+    pub temperature: Filter,
+    pub humidity: Filter,
+    pub pressure: Filter,
+    pub gas: Filter,
+}
+
+impl ReadingSmoother {
+    pub fn new(smoothing: Smoothing) -> Self {
+        ReadingSmoother {
+            temperature: Filter::new(smoothing),
+            humidity: Filter::new(smoothing),
+            pressure: Filter::new(smoothing),
+            gas: Filter::new(smoothing),
+        }
+    }
+
+    /// Smooth `reading` in place. Gas resistances are only smoothed at a fixed heater
+    /// temperature, since those at different temperatures aren't comparable.
+    pub fn apply(&mut self, reading: &mut Reading) {
+        let timestamp = reading.timestamp;
+        reading.temperature_c = self.temperature.push(timestamp, reading.temperature_c);
+        reading.humidity_percent = self.humidity.push(timestamp, reading.humidity_percent);
+        reading.pressure_hpa = self.pressure.push(timestamp, reading.pressure_hpa);
+        if reading.heater_temp_c.is_none() {
+            let gas = &mut self.gas;
+            reading.gas_ohms = reading
+                .gas_ohms
+                .map(|gas_ohms| gas.push(timestamp, gas_ohms));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moving_average_over_a_known_sequence() {
+        let mut average = MovingAverage::new(3);
+        let smoothed: Vec<f64> = [3.0, 6.0, 9.0, 12.0, 3.0]
+            .iter()
+            .map(|&value| average.push(value))
+            .collect();
+        // Partial averages until the window fills up, then the last three.
+        assert_eq!(smoothed, vec![3.0, 4.5, 6.0, 9.0, 8.0]);
+    }
+
+    #[test]
+    fn moving_average_of_one_passes_values_through() {
+        let mut average = MovingAverage::new(1);
+        for &value in &[21.5, -3.0, 1013.25, 0.0] {
+            assert_eq!(average.push(value), value);
+        }
+    }
+
+    #[test]
+    fn smoothing_window_of_zero_is_off() {
+        assert_eq!(parse_smoothing("sma", 0, 0.5, 0), Ok(None));
+        assert_eq!(
+            parse_smoothing("sma", 5, 0.5, 0),
+            Ok(Some(Smoothing::MovingAverage(5)))
+        );
+    }
+
+    #[test]
+    fn ema_passes_the_first_value_through() {
+        let mut ema = EmaFilter::new(0.2);
+        assert_eq!(ema.push(21.5), 21.5);
+        assert_eq!(ema.push(21.5), 21.5);
+    }
+
+    #[test]
+    fn ema_converges_on_a_step() {
+        let mut ema = EmaFilter::new(0.5);
+        ema.push(10.0);
+        let smoothed: Vec<f64> = (0..4).map(|_| ema.push(20.0)).collect();
+        assert_eq!(smoothed, vec![15.0, 17.5, 18.75, 19.375]);
+        let mut ema = EmaFilter::new(0.1);
+        ema.push(0.0);
+        let last = (0..100).map(|_| ema.push(100.0)).last().unwrap();
+        assert!((last - 100.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn ema_alpha_is_validated() {
+        assert_eq!(
+            parse_smoothing("ema", 0, 0.3, 0),
+            Ok(Some(Smoothing::Exponential(0.3)))
+        );
+        assert!(parse_smoothing("ema", 0, 0.0, 0).is_err());
+        assert!(parse_smoothing("ema", 0, 1.5, 0).is_err());
+        assert_eq!(EmaFilter::new(1.0).push(5.0), 5.0);
+    }
+
+    const SECOND: i64 = 1_000_000_000;
+
+    #[test]
+    fn time_window_evicts_values_older_than_the_window() {
+        let mut mean = TimeWindowMean::new(Duration::from_secs(10));
+        assert_eq!(mean.push(0, 10.0), 10.0);
+        assert_eq!(mean.push(4 * SECOND, 20.0), 15.0);
+        assert_eq!(mean.push(9 * SECOND, 30.0), 20.0);
+        // Exactly one window after the first value, so that one is out.
+        assert_eq!(mean.push(10 * SECOND, 40.0), 30.0);
+        assert_eq!(mean.push(19 * SECOND, 50.0), 45.0);
+    }
+
+    #[test]
+    fn time_window_follows_irregular_intervals() {
+        let mut mean = TimeWindowMean::new(Duration::from_secs(60));
+        for second in 0..30 {
+            mean.push(second * SECOND, 0.0);
+        }
+        // A long gap leaves only the current value.
+        assert_eq!(mean.push(120 * SECOND, 8.0), 8.0);
+        assert_eq!(mean.push(121 * SECOND, 4.0), 6.0);
+    }
+
+    #[test]
+    fn time_window_starts_over_when_reset_or_the_clock_goes_back() {
+        let mut mean = TimeWindowMean::new(Duration::from_secs(60));
+        mean.push(100 * SECOND, 10.0);
+        mean.push(101 * SECOND, 20.0);
+        assert_eq!(mean.push(50 * SECOND, 3.0), 3.0);
+        mean.reset();
+        assert_eq!(mean.push(51 * SECOND, 7.0), 7.0);
+    }
+
+    #[test]
+    fn window_smoothing_needs_a_window() {
+        assert_eq!(
+            parse_smoothing("window", 0, 0.0, 300),
+            Ok(Some(Smoothing::TimeWindow(Duration::from_secs(300))))
+        );
+        assert!(parse_smoothing("window", 5, 0.5, 0).is_err());
+    }
+}
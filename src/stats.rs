@@ -0,0 +1,83 @@
+//! Operational counters about the daemon itself, for alerting when a node stops reporting.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters shared between the read loop and the metrics endpoint.
+#[derive(Debug, Default)]
+pub struct Stats {
+    reads: AtomicU64,
+    read_errors: AtomicU64,
+    writes: AtomicU64,
+    write_errors: AtomicU64,
+    buffered_points: AtomicU64,
+}
+
+/// The counters at one point in time.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StatsSnapshot {
+    pub reads_total: u64,
+    pub read_errors_total: u64,
+    pub writes_total: u64,
+    pub write_errors_total: u64,
+    pub buffered_points: u64,
+}
+
+impl Stats {
+    pub fn record_read(&self) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_read_error(&self) {
+        self.read_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a write to the sink, successful or not.
+    pub fn record_write(&self, ok: bool) {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        if !ok {
+            self.write_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn set_buffered_points(&self, points: usize) {
+        self.buffered_points.store(points as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            reads_total: self.reads.load(Ordering::Relaxed),
+            read_errors_total: self.read_errors.load(Ordering::Relaxed),
+            writes_total: self.writes.load(Ordering::Relaxed),
+            write_errors_total: self.write_errors.load(Ordering::Relaxed),
+            buffered_points: self.buffered_points.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_follow_successes_and_failures() {
+        let stats = Stats::default();
+        stats.record_read();
+        stats.record_read();
+        stats.record_read_error();
+        stats.record_write(true);
+        stats.record_write(false);
+        stats.set_buffered_points(12);
+        assert_eq!(
+            stats.snapshot(),
+            StatsSnapshot {
+                reads_total: 2,
+                read_errors_total: 1,
+                writes_total: 2,
+                write_errors_total: 1,
+                buffered_points: 12,
+            }
+        );
+        stats.set_buffered_points(0);
+        assert_eq!(stats.snapshot().buffered_points, 0);
+    }
+}
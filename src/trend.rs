@@ -0,0 +1,127 @@
+//! Rates of change between readings.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+const NANOS_PER_HOUR: f64 = 3_600_000_000_000.0;
+
+/// Readings closer together than this are too noisy to divide by.
+const MIN_GAP: Duration = Duration::from_secs(1);
+
+/// Readings further apart than this are treated as a fresh start rather than a trend.
+const MAX_GAP: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Keeps the previous value of a measurement to report its change per hour.
+#[derive(Debug, Clone, Default)]
+pub struct Trend {
+    previous: Option<(i64, f64)>,
+}
+
+impl Trend {
+    pub fn new() -> Self {
+        Trend::default()
+    }
+
+    /// Record `value` at `timestamp` (nanoseconds since the epoch) and return the change per
+    /// hour since the previous value. Returns `None` for the first value, after a gap longer
+    /// than six hours, and for values less than a second after the previous one, which are
+    /// skipped so the next one is compared against the same baseline.
+    pub fn update(&mut self, timestamp: i64, value: f64) -> Option<f64> {
+        let (previous_timestamp, previous_value) = match self.previous {
+            Some(previous) => previous,
+            None => {
+                self.previous = Some((timestamp, value));
+                return None;
+            }
+        };
+        let elapsed = timestamp.saturating_sub(previous_timestamp);
+        if elapsed < MIN_GAP.as_nanos() as i64 {
+            return None;
+        }
+        self.previous = Some((timestamp, value));
+        if elapsed > MAX_GAP.as_nanos() as i64 {
+            return None;
+        }
+        Some((value - previous_value) * NANOS_PER_HOUR / elapsed as f64)
+    }
+
+    /// Forget the previous value, e.g. after the sensor was re-initialized.
+    pub fn reset(&mut self) {
+        self.previous = None;
+    }
+}
+
+/// Keeps the values of a measurement over a span of time to report how much it changed across
+/// the whole span, e.g. the three-hour pressure tendency.
+#[derive(Debug, Clone)]
+pub struct History {
+    span: Duration,
+    /// Oldest first. Only the newest value at least `span` old is kept beyond the span.
+    samples: VecDeque<(i64, f64)>,
+}
+
+impl History {
+    pub fn new(span: Duration) -> Self {
+        History {
+            span,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Record `value` at `timestamp` (nanoseconds since the epoch) and return the change since
+    /// the value one span earlier. Returns `None` until a full span has been seen, and when the
+    /// closest older value is more than two spans back.
+    pub fn update(&mut self, timestamp: i64, value: f64) -> Option<f64> {
+        let span = self.span.as_nanos() as i64;
+        self.samples.push_back((timestamp, value));
+        while self.samples.len() > 1 && timestamp.saturating_sub(self.samples[1].0) >= span {
+            self.samples.pop_front();
+        }
+        let (oldest_timestamp, oldest_value) = self.samples[0];
+        let elapsed = timestamp.saturating_sub(oldest_timestamp);
+        if elapsed >= span && elapsed <= span.saturating_mul(2) {
+            Some(value - oldest_value)
+        } else {
+            None
+        }
+    }
+
+    /// Forget all values, e.g. after the sensor was re-initialized.
+    pub fn reset(&mut self) {
+        self.samples.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: i64 = 60_000_000_000;
+
+    #[test]
+    fn trend_is_the_change_per_hour() {
+        let mut trend = Trend::new();
+        assert_eq!(trend.update(0, 1013.0), None);
+        assert_eq!(trend.update(30 * MINUTE, 1012.0), Some(-2.0));
+        assert_eq!(trend.update(90 * MINUTE, 1012.5), Some(0.5));
+    }
+
+    #[test]
+    fn trend_skips_readings_too_close_together() {
+        let mut trend = Trend::new();
+        trend.update(0, 20.0);
+        assert_eq!(trend.update(MINUTE / 120, 25.0), None);
+        // Still compared against the first value.
+        assert_eq!(trend.update(60 * MINUTE, 21.0), Some(1.0));
+    }
+
+    #[test]
+    fn trend_starts_over_after_a_long_gap_or_reset() {
+        let mut trend = Trend::new();
+        trend.update(0, 1013.0);
+        assert_eq!(trend.update(7 * 60 * MINUTE, 1000.0), None);
+        assert_eq!(trend.update(8 * 60 * MINUTE, 1001.0), Some(1.0));
+        trend.reset();
+        assert_eq!(trend.update(9 * 60 * MINUTE, 1002.0), None);
+    }
+}
@@ -0,0 +1,228 @@
+//! Unit conversions and rounding for emitted measurements.
+
+/// Hectopascals per inch of mercury.
+const HPA_PER_INHG: f64 = 33.863_886;
+/// Hectopascals per millimetre of mercury.
+const HPA_PER_MMHG: f64 = 1.333_224;
+
+/// Unit temperatures are written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TemperatureUnit {
+    Celsius,
+    Fahrenheit,
+}
+
+impl TemperatureUnit {
+    /// Suffix used in measurement names, e.g. `temperature_f`.
+    pub fn suffix(self) -> &'static str {
+        match self {
+            TemperatureUnit::Celsius => "c",
+            TemperatureUnit::Fahrenheit => "f",
+        }
+    }
+
+    /// Convert a temperature in °C to this unit.
+    pub fn convert(self, celsius: f64) -> f64 {
+        match self {
+            TemperatureUnit::Celsius => celsius,
+            TemperatureUnit::Fahrenheit => celsius_to_fahrenheit(celsius),
+        }
+    }
+
+    /// Convert a temperature difference in °C to this unit, without the zero-point shift.
+    pub fn convert_delta(self, celsius: f64) -> f64 {
+        match self {
+            TemperatureUnit::Celsius => celsius,
+            TemperatureUnit::Fahrenheit => celsius * 9.0 / 5.0,
+        }
+    }
+}
+
+/// Parse a temperature unit, `c` or `f`.
+pub fn parse_temperature_unit(value: &str) -> Result<TemperatureUnit, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "c" | "celsius" => Ok(TemperatureUnit::Celsius),
+        "f" | "fahrenheit" => Ok(TemperatureUnit::Fahrenheit),
+        _ => Err(format!(
+            "invalid temperature unit {:?}, expected c or f",
+            value
+        )),
+    }
+}
+
+pub fn celsius_to_fahrenheit(celsius: f64) -> f64 {
+    celsius * 9.0 / 5.0 + 32.0
+}
+
+/// Unit pressures are written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PressureUnit {
+    Hectopascal,
+    InchesOfMercury,
+    MillimetresOfMercury,
+}
+
+impl PressureUnit {
+    /// Suffix used in measurement names, e.g. `pressure_inhg`.
+    pub fn suffix(self) -> &'static str {
+        match self {
+            PressureUnit::Hectopascal => "hpa",
+            PressureUnit::InchesOfMercury => "inhg",
+            PressureUnit::MillimetresOfMercury => "mmhg",
+        }
+    }
+
+    /// Convert a pressure in hPa to this unit.
+    pub fn convert(self, hpa: f64) -> f64 {
+        match self {
+            PressureUnit::Hectopascal => hpa,
+            PressureUnit::InchesOfMercury => hpa / HPA_PER_INHG,
+            PressureUnit::MillimetresOfMercury => hpa / HPA_PER_MMHG,
+        }
+    }
+}
+
+/// Parse a pressure unit, `hpa`, `inhg` or `mmhg`.
+pub fn parse_pressure_unit(value: &str) -> Result<PressureUnit, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "hpa" => Ok(PressureUnit::Hectopascal),
+        "inhg" => Ok(PressureUnit::InchesOfMercury),
+        "mmhg" => Ok(PressureUnit::MillimetresOfMercury),
+        _ => Err(format!(
+            "invalid pressure unit {:?}, expected hpa, inhg or mmhg",
+            value
+        )),
+    }
+}
+
+/// Unit gas resistance is written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GasUnit {
+    Ohm,
+    Kilohm,
+}
+
+impl GasUnit {
+    /// Measurement name for gas resistance in this unit.
+    pub fn measurement(self) -> &'static str {
+        match self {
+            GasUnit::Ohm => "gas_resistance_ohms",
+            GasUnit::Kilohm => "gas_resistance_kohm",
+        }
+    }
+
+    /// Convert a resistance in ohms to this unit.
+    pub fn convert(self, ohms: f64) -> f64 {
+        match self {
+            GasUnit::Ohm => ohms,
+            GasUnit::Kilohm => ohms / 1000.0,
+        }
+    }
+}
+
+/// Parse a gas resistance unit, `ohm` or `kohm`.
+pub fn parse_gas_unit(value: &str) -> Result<GasUnit, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "ohm" | "ohms" => Ok(GasUnit::Ohm),
+        "kohm" | "kohms" => Ok(GasUnit::Kilohm),
+        _ => Err(format!(
+            "invalid gas unit {:?}, expected ohm or kohm",
+            value
+        )),
+    }
+}
+
+/// Round to `places` decimal places, breaking ties towards the even digit so rounding doesn't
+/// bias averages up.
+pub fn round_half_even(value: f64, places: u32) -> f64 {
+    if !value.is_finite() {
+        return value;
+    }
+    let factor = 10f64.powi(places as i32);
+    let scaled = value * factor;
+    let floor = scaled.floor();
+    let rounded = if (scaled - floor - 0.5).abs() < 1e-9 {
+        if floor % 2.0 == 0.0 {
+            floor
+        } else {
+            floor + 1.0
+        }
+    } else {
+        scaled.round()
+    };
+    rounded / factor
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} is not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn celsius_converts_to_fahrenheit() {
+        assert_close(celsius_to_fahrenheit(0.0), 32.0, 1e-9);
+        assert_close(celsius_to_fahrenheit(100.0), 212.0, 1e-9);
+        assert_close(celsius_to_fahrenheit(-40.0), -40.0, 1e-9);
+        assert_close(TemperatureUnit::Fahrenheit.convert(21.5), 70.7, 1e-9);
+        assert_close(TemperatureUnit::Fahrenheit.convert_delta(10.0), 18.0, 1e-9);
+        assert_eq!(TemperatureUnit::Celsius.convert(21.5), 21.5);
+        assert_eq!(
+            parse_temperature_unit(" F "),
+            Ok(TemperatureUnit::Fahrenheit)
+        );
+        assert!(parse_temperature_unit("k").is_err());
+    }
+
+    #[test]
+    fn standard_pressure_converts_to_mercury() {
+        assert_close(PressureUnit::InchesOfMercury.convert(1013.25), 29.92, 0.005);
+        assert_close(
+            PressureUnit::MillimetresOfMercury.convert(1013.25),
+            760.0,
+            0.005,
+        );
+        assert_eq!(PressureUnit::Hectopascal.convert(1013.25), 1013.25);
+        assert_eq!(
+            parse_pressure_unit("inHg"),
+            Ok(PressureUnit::InchesOfMercury)
+        );
+        assert!(parse_pressure_unit("psi").is_err());
+    }
+
+    #[test]
+    fn rounding_breaks_ties_towards_even() {
+        assert_eq!(round_half_even(21.34999999, 2), 21.35);
+        assert_eq!(round_half_even(0.125, 2), 0.12);
+        assert_eq!(round_half_even(0.375, 2), 0.38);
+        assert_eq!(round_half_even(2.5, 0), 2.0);
+        assert_eq!(round_half_even(3.5, 0), 4.0);
+        assert_eq!(round_half_even(1013.2549, 1), 1013.3);
+    }
+
+    #[test]
+    fn rounding_negative_temperatures() {
+        assert_eq!(round_half_even(-2.5, 0), -2.0);
+        assert_eq!(round_half_even(-3.5, 0), -4.0);
+        assert_eq!(round_half_even(-12.345, 1), -12.3);
+        assert_eq!(round_half_even(-0.25, 1), -0.2);
+        assert!(round_half_even(f64::NAN, 2).is_nan());
+    }
+
+    #[test]
+    fn gas_resistance_converts_to_kilohms() {
+        assert_eq!(GasUnit::Kilohm.convert(125_000.0), 125.0);
+        assert_eq!(GasUnit::Ohm.convert(125_000.0), 125_000.0);
+        assert_eq!(GasUnit::Kilohm.measurement(), "gas_resistance_kohm");
+        assert_eq!(parse_gas_unit("kOhm"), Ok(GasUnit::Kilohm));
+        assert!(parse_gas_unit("mohm").is_err());
+    }
+}
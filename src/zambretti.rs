@@ -0,0 +1,136 @@
+//! The Zambretti forecaster: a short-term weather forecast from sea-level pressure, its
+//! three-hour tendency and the season.
+
+/// Three-hour pressure change beyond which pressure counts as rising or falling, in hPa.
+const TENDENCY_THRESHOLD_HPA: f64 = 1.6;
+
+/// Forecasts A to Z of the original Negretti & Zambra table.
+const FORECASTS: [&str; 26] = [
+    "Settled fine",
+    "Fine weather",
+    "Becoming fine",
+    "Fine, becoming less settled",
+    "Fine, possible showers",
+    "Fairly fine, improving",
+    "Fairly fine, possible showers early",
+    "Fairly fine, showery later",
+    "Showery early, improving",
+    "Changeable, mending",
+    "Fairly fine, showers likely",
+    "Rather unsettled clearing later",
+    "Unsettled, probably improving",
+    "Showery, bright intervals",
+    "Showery, becoming less settled",
+    "Changeable, some rain",
+    "Unsettled, short fine intervals",
+    "Unsettled, rain later",
+    "Unsettled, some rain",
+    "Mostly very unsettled",
+    "Occasional rain, worsening",
+    "Rain at times, very unsettled",
+    "Rain at frequent intervals",
+    "Rain, very unsettled",
+    "Stormy, may improve",
+    "Stormy, much rain",
+];
+
+/// Forecast letter, as an index into `FORECASTS`, for each code. Falling pressure gives codes
+/// 1-9, steady 10-19 and rising 20-32.
+const FORECAST_BY_CODE: [usize; 32] = [
+    0, 1, 3, 7, 14, 17, 20, 23, 25, // falling
+    0, 1, 4, 10, 13, 15, 18, 22, 23, 25, // steady
+    0, 1, 2, 5, 6, 8, 9, 11, 12, 16, 19, 24, 25, // rising
+];
+
+/// Which way pressure is heading over three hours.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tendency {
+    Falling,
+    Steady,
+    Rising,
+}
+
+/// Classify a three-hour pressure change in hPa.
+pub fn tendency(change_hpa: f64) -> Tendency {
+    if change_hpa <= -TENDENCY_THRESHOLD_HPA {
+        Tendency::Falling
+    } else if change_hpa >= TENDENCY_THRESHOLD_HPA {
+        Tendency::Rising
+    } else {
+        Tendency::Steady
+    }
+}
+
+/// Whether `month` (1-12) falls in the summer half of the year, April to September in the
+/// northern hemisphere.
+pub fn is_summer(month: u32, southern_hemisphere: bool) -> bool {
+    let northern_summer = (4..=9).contains(&month);
+    northern_summer != southern_hemisphere
+}
+
+/// Zambretti code 1-32 for a sea-level pressure in hPa, lower codes forecasting better weather.
+/// Outside of summer a rising or falling forecast is one step worse.
+pub fn zambretti_code(sea_level_hpa: f64, tendency: Tendency, summer: bool) -> u8 {
+    let season = if summer { 0.0 } else { 1.0 };
+    let (z, min, max) = match tendency {
+        Tendency::Falling => (127.0 - 0.12 * sea_level_hpa + season, 1.0, 9.0),
+        Tendency::Steady => (144.0 - 0.13 * sea_level_hpa, 10.0, 19.0),
+        Tendency::Rising => (185.0 - 0.16 * sea_level_hpa + season, 20.0, 32.0),
+    };
+    z.round().max(min).min(max) as u8
+}
+
+/// The forecast for a code from [`zambretti_code`].
+pub fn forecast_text(code: u8) -> &'static str {
+    let index = (code.clamp(1, 32) - 1) as usize;
+    FORECASTS[FORECAST_BY_CODE[index]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tendency_needs_a_change_beyond_the_threshold() {
+        assert_eq!(tendency(-2.0), Tendency::Falling);
+        assert_eq!(tendency(-1.0), Tendency::Steady);
+        assert_eq!(tendency(0.0), Tendency::Steady);
+        assert_eq!(tendency(1.6), Tendency::Rising);
+    }
+
+    #[test]
+    fn falling_pressure_forecasts() {
+        let code = zambretti_code(1010.0, Tendency::Falling, true);
+        assert_eq!(code, 6);
+        assert_eq!(forecast_text(code), "Unsettled, rain later");
+        assert_eq!(zambretti_code(1010.0, Tendency::Falling, false), 7);
+        assert_eq!(zambretti_code(950.0, Tendency::Falling, true), 9);
+    }
+
+    #[test]
+    fn steady_pressure_forecasts() {
+        let code = zambretti_code(1020.0, Tendency::Steady, true);
+        assert_eq!(code, 11);
+        assert_eq!(forecast_text(code), "Fine weather");
+        // The season only shifts rising and falling forecasts.
+        assert_eq!(zambretti_code(1020.0, Tendency::Steady, false), 11);
+    }
+
+    #[test]
+    fn rising_pressure_forecasts() {
+        let code = zambretti_code(1030.0, Tendency::Rising, true);
+        assert_eq!(code, 20);
+        assert_eq!(forecast_text(code), "Settled fine");
+        let code = zambretti_code(1000.0, Tendency::Rising, false);
+        assert_eq!(code, 26);
+        assert_eq!(forecast_text(code), "Changeable, mending");
+    }
+
+    #[test]
+    fn summer_flips_in_the_southern_hemisphere() {
+        assert!(is_summer(7, false));
+        assert!(!is_summer(7, true));
+        assert!(is_summer(1, true));
+        assert!(!is_summer(10, false));
+    }
+}